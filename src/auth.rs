@@ -0,0 +1,295 @@
+use url::Url;
+
+use crate::{
+    config::RelayConfig,
+    connection::{AuthedPubkeys, Connection},
+    event::{Event, EventKind},
+    message::ReasonPrefix,
+    req::Filter,
+    validation::Rejection,
+};
+
+// 認証イベントのcreated_atと現在時刻のずれの許容範囲（秒）
+const AUTH_EVENT_MAX_AGE: i64 = 10 * 60;
+
+// NIP-42の認証イベント(kind 22242)を検証する
+// 署名に加えて、接続のチャレンジ・リレーのURL・created_atが現在時刻に近いことを確認する
+// リレーのURLが設定されていない場合は接続時のHostヘッダーと照合し、どちらもない場合は受け付けない
+pub fn verify_auth_event(
+    event: &Event,
+    challenge: &str,
+    relay_url: Option<&str>,
+    host: Option<&str>,
+    now: i64,
+) -> Result<(), Rejection> {
+    if event.kind != EventKind::CLIENT_AUTH {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "auth event must be kind 22242",
+        ));
+    }
+    event
+        .verify()
+        .map_err(|_| Rejection::new(ReasonPrefix::Invalid, "auth event has a bad signature"))?;
+    if (now - event.created_at).abs() > AUTH_EVENT_MAX_AGE {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "auth event is too old or too far in the future",
+        ));
+    }
+    if event.tag_value("challenge") != Some(challenge) {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "auth event challenge does not match",
+        ));
+    }
+    let relay = event.tag_value("relay");
+    let matches = match (relay_url, host) {
+        (Some(relay_url), _) => relay.is_some_and(|url| same_relay_url(url, relay_url)),
+        (None, Some(host)) => relay.is_some_and(|url| same_relay_host(url, host)),
+        (None, None) => false,
+    };
+    if !matches {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "auth event relay does not match",
+        ));
+    }
+    Ok(())
+}
+
+// スキーム・ホスト・ポート・パスが一致すれば同じリレーとみなす
+fn same_relay_url(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => {
+            a.scheme() == b.scheme()
+                && a.host_str() == b.host_str()
+                && a.port_or_known_default() == b.port_or_known_default()
+                && a.path().trim_end_matches('/') == b.path().trim_end_matches('/')
+        }
+        _ => false,
+    }
+}
+
+// Hostヘッダーとホスト名・ポートが一致すれば同じリレーとみなす
+// スキームはTLSを終端するプロキシの有無で変わるため比較しない
+fn same_relay_host(url: &str, host: &str) -> bool {
+    match (Url::parse(url), Url::parse(&format!("ws://{host}"))) {
+        (Ok(url), Ok(host)) => {
+            url.host_str() == host.host_str()
+                && match host.port() {
+                    Some(port) => url.port_or_known_default() == Some(port),
+                    None => url.port().is_none(),
+                }
+        }
+        _ => false,
+    }
+}
+
+// リレーのポリシーに従って、接続がREQやEVENTを送信できるかどうかを確認する
+pub fn authorize(connection: &Connection, config: &RelayConfig) -> Result<(), Rejection> {
+    if !config.auth_required {
+        return Ok(());
+    }
+    let authed = connection.authed_pubkeys();
+    if authed.is_empty() {
+        return Err(Rejection::new(
+            ReasonPrefix::AuthRequired,
+            "authentication required",
+        ));
+    }
+    if !config.allowed_pubkeys.is_empty()
+        && !authed.iter().any(|p| config.allowed_pubkeys.contains(p))
+    {
+        return Err(Rejection::new(
+            ReasonPrefix::Restricted,
+            "this relay only serves its members",
+        ));
+    }
+    Ok(())
+}
+
+// NIP-70: 保護されたイベントは作成者として認証した接続からのみ受け付ける
+pub fn authorize_protected(connection: &Connection, event: &Event) -> Result<(), Rejection> {
+    if !event.is_protected() || connection.is_authenticated_as(&event.pubkey) {
+        return Ok(());
+    }
+    if connection.authed_pubkeys().is_empty() {
+        return Err(Rejection::new(
+            ReasonPrefix::AuthRequired,
+            "this event may only be published by its author",
+        ));
+    }
+    Err(Rejection::new(
+        ReasonPrefix::Restricted,
+        "this event may only be published by its author",
+    ))
+}
+
+// NIP-17/NIP-59: gift wrap(kind 1059)は"p"タグの受信者として認証した接続にのみ送信する
+pub fn can_receive(authed: &AuthedPubkeys, event: &Event) -> bool {
+    event.kind != EventKind::GIFT_WRAP
+        || event
+            .tags
+            .iter()
+            .filter(|t| t.first().is_some_and(|n| n == "p"))
+            .filter_map(|t| t.get(1))
+            .any(|pubkey| authed.contains(pubkey))
+}
+
+// gift wrapを明示的に要求するREQ・COUNTには、先に認証するよう求める
+pub fn authorize_gift_wraps(connection: &Connection, filters: &[Filter]) -> Result<(), Rejection> {
+    let gift_wrap = u16::from(EventKind::GIFT_WRAP);
+    let requested = filters.iter().any(|f| {
+        f.kinds
+            .as_ref()
+            .is_some_and(|kinds| kinds.contains(&gift_wrap))
+    });
+    if requested && connection.authed_pubkeys().is_empty() {
+        return Err(Rejection::new(
+            ReasonPrefix::AuthRequired,
+            "gift wraps are only served to their recipients",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::{authorize_gift_wraps, authorize_protected, can_receive, verify_auth_event};
+    use crate::{
+        connection::Connection,
+        event::{Event, EventKind},
+        message::ReasonPrefix,
+        req::Filter,
+        test_util::{signed_event, ALICE},
+    };
+
+    const NOW: i64 = 1708838939;
+    const RELAY: &str = "wss://relay.example.com";
+
+    fn auth_event(kind: EventKind, challenge: &str, relay: &str, created_at: i64) -> Event {
+        signed_event(
+            ALICE,
+            kind.into(),
+            vec![vec!["relay", relay], vec!["challenge", challenge]],
+            "",
+            created_at,
+        )
+    }
+
+    fn verify(event: &Event) -> Result<(), ReasonPrefix> {
+        verify_auth_event(event, "challenge", Some(RELAY), None, NOW).map_err(|r| r.prefix)
+    }
+
+    #[test]
+    fn accept_valid_auth_event() {
+        let event = auth_event(EventKind::CLIENT_AUTH, "challenge", RELAY, NOW);
+        assert_eq!(verify(&event), Ok(()));
+        // 末尾のスラッシュやホスト名の大文字小文字の違いは許容する
+        let event = auth_event(
+            EventKind::CLIENT_AUTH,
+            "challenge",
+            "wss://Relay.Example.com/",
+            NOW - 60,
+        );
+        assert_eq!(verify(&event), Ok(()));
+    }
+
+    #[test]
+    fn reject_invalid_auth_event() {
+        for event in [
+            auth_event(EventKind::TEXT_NOTE, "challenge", RELAY, NOW),
+            auth_event(EventKind::CLIENT_AUTH, "other", RELAY, NOW),
+            auth_event(
+                EventKind::CLIENT_AUTH,
+                "challenge",
+                "wss://other.example.com",
+                NOW,
+            ),
+            auth_event(EventKind::CLIENT_AUTH, "challenge", RELAY, NOW - 3600),
+        ] {
+            assert_eq!(verify(&event), Err(ReasonPrefix::Invalid));
+        }
+    }
+
+    #[test]
+    fn check_host_when_relay_url_is_unset() {
+        let verify_with_host = |relay: &str, host: Option<&str>| {
+            let event = auth_event(EventKind::CLIENT_AUTH, "challenge", relay, NOW);
+            verify_auth_event(&event, "challenge", None, host, NOW).map_err(|r| r.prefix)
+        };
+        assert_eq!(verify_with_host(RELAY, Some("relay.example.com")), Ok(()));
+        assert_eq!(
+            verify_with_host(
+                "ws://relay.example.com:7000/",
+                Some("relay.example.com:7000")
+            ),
+            Ok(())
+        );
+        // 別のリレー宛ての認証イベントは受け付けない
+        for (relay, host) in [
+            ("wss://other.example.com", Some("relay.example.com")),
+            ("wss://relay.example.com:7000", Some("relay.example.com")),
+            (RELAY, Some("relay.example.com:7000")),
+            (RELAY, None),
+        ] {
+            assert_eq!(
+                verify_with_host(relay, host),
+                Err(ReasonPrefix::Invalid),
+                "{relay} {host:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn accept_protected_event_only_from_author() {
+        let event = auth_event(EventKind::TEXT_NOTE, "challenge", RELAY, NOW);
+        let (sender, _receiver) = unbounded_channel();
+        let conn = Connection::new(SocketAddr::from(([127, 0, 0, 1], 1)), sender);
+        // 保護されていないイベントは認証なしで受け付ける
+        assert_eq!(authorize_protected(&conn, &event), Ok(()));
+
+        let mut protected = event.clone();
+        protected.tags.push(vec!["-".to_string()]);
+        let prefix =
+            |conn: &Connection| authorize_protected(conn, &protected).map_err(|r| r.prefix);
+        assert_eq!(prefix(&conn), Err(ReasonPrefix::AuthRequired));
+        conn.authenticate("0".repeat(64));
+        assert_eq!(prefix(&conn), Err(ReasonPrefix::Restricted));
+        conn.authenticate(event.pubkey.clone());
+        assert_eq!(prefix(&conn), Ok(()));
+    }
+
+    #[test]
+    fn serve_gift_wraps_only_to_recipient() {
+        let (sender, _receiver) = unbounded_channel();
+        let conn = Connection::new(SocketAddr::from(([127, 0, 0, 1], 1)), sender);
+        let recipient = "0".repeat(63) + "3";
+        let mut wrap = auth_event(EventKind::GIFT_WRAP, "challenge", RELAY, NOW);
+        wrap.tags = vec![vec!["p".to_string(), recipient.clone()]];
+        let note = auth_event(EventKind::TEXT_NOTE, "challenge", RELAY, NOW);
+
+        let filters = [Filter::new().kinds(vec![1059])];
+        assert_eq!(
+            authorize_gift_wraps(&conn, &filters).map_err(|r| r.prefix),
+            Err(ReasonPrefix::AuthRequired)
+        );
+        assert_eq!(authorize_gift_wraps(&conn, &[Filter::new()]), Ok(()));
+        let authed = conn.shared_authed_pubkeys();
+        assert!(can_receive(&authed, &note));
+        assert!(!can_receive(&authed, &wrap));
+
+        // 受信者以外として認証しても受け取れない
+        conn.authenticate(note.pubkey.clone());
+        assert_eq!(authorize_gift_wraps(&conn, &filters), Ok(()));
+        assert!(!can_receive(&authed, &wrap));
+        // 認証状態は共有されているので、後から認証しても反映される
+        conn.authenticate(recipient);
+        assert!(can_receive(&authed, &wrap));
+    }
+}
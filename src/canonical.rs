@@ -0,0 +1,170 @@
+use std::fmt::Write;
+
+use crate::event::EventKind;
+
+// NIP-01で定められたイベントIDの計算に使うシリアライズ形式
+// [0,<pubkey>,<created_at>,<kind>,<tags>,<content>] を空白なしのJSONで出力する
+pub fn serialize_event(
+    pubkey: &str,
+    created_at: i64,
+    kind: EventKind,
+    tags: &[Vec<String>],
+    content: &str,
+) -> String {
+    let mut out = String::with_capacity(128 + content.len());
+    out.push_str("[0,");
+    write_string(&mut out, pubkey);
+    let _ = write!(out, ",{},{},", created_at, u16::from(kind));
+    out.push('[');
+    for (i, tag) in tags.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push('[');
+        for (j, value) in tag.iter().enumerate() {
+            if j > 0 {
+                out.push(',');
+            }
+            write_string(&mut out, value);
+        }
+        out.push(']');
+    }
+    out.push_str("],");
+    write_string(&mut out, content);
+    out.push(']');
+    out
+}
+
+// JSON文字列としてエスケープして書き出す
+// NIP-01の規定どおり \n " \ \r \t \b \f は短縮形でエスケープし、その他の文字はUTF-8のまま出力する
+// NIP-01ではそれ以外の文字もそのまま出力するとされているが、残りの制御文字は
+// クライアントがIDを計算する際のJSON.stringifyやserde_jsonの出力に合わせて意図的に\u00XX形式にする
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serialize_event;
+    use crate::event::{Event, EventKind, UnsignedEvent};
+
+    const PUBKEY: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    // (content, シリアライズ結果, id)
+    // 公開されたイベントではなくこのリポジトリで作成したもので、idはPythonの
+    // json.dumps(separators=(",", ":"), ensure_ascii=False)とSHA-256でも同じ値になることを確認している
+    // 非ASCIIや絵文字を含む公開イベントとの照合はしていない
+    fn vectors() -> Vec<(&'static str, &'static str, &'static str)> {
+        vec![
+            (
+                "hello",
+                r#"[0,"79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",1700000000,1,[["t","nostr"]],"hello"]"#,
+                "ad323a4e58f417eab1918be103889ee951f7bb23f69e62d767d183eca4f64434",
+            ),
+            (
+                "line1\nline2\r\n\t\"quoted\" \\ back\u{08}\u{0c}",
+                r#"[0,"79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",1700000000,1,[["t","nostr"]],"line1\nline2\r\n\t\"quoted\" \\ back\b\f"]"#,
+                "14ba82d22b909dda755d3c9a4011bd60e459f738178b8ab92ce33172b5d1e19e",
+            ),
+            (
+                "ctrl\u{01}\u{1f}",
+                r#"[0,"79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",1700000000,1,[["t","nostr"]],"ctrl\u0001\u001f"]"#,
+                "e79b8f3cac7f206146d3883abc0c5bf59b4111919cfb8bf55b9ea3b7f56d7874",
+            ),
+            (
+                "こんにちは 🤙🏻 / <&>",
+                r#"[0,"79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",1700000000,1,[["t","nostr"]],"こんにちは 🤙🏻 / <&>"]"#,
+                "65b5afa2b5e89dd81cede5453825a222e8bf33ac37dfe3071310dc74ef6d3f2e",
+            ),
+        ]
+    }
+
+    fn tags() -> Vec<Vec<String>> {
+        vec![vec!["t".to_string(), "nostr".to_string()]]
+    }
+
+    #[test]
+    fn serialize_vectors() {
+        for (content, expected, _) in vectors() {
+            assert_eq!(
+                serialize_event(PUBKEY, 1700000000, EventKind::TEXT_NOTE, &tags(), content),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn event_id_vectors() {
+        for (content, _, id) in vectors() {
+            let event = UnsignedEvent::new(
+                PUBKEY.to_string(),
+                EventKind::TEXT_NOTE,
+                tags(),
+                content.to_string(),
+                1700000000,
+            );
+            assert_eq!(event.id(), id);
+        }
+    }
+
+    #[test]
+    fn escape_tag_values() {
+        let tags = vec![vec!["e".to_string(), "a\"b\\c\n".to_string()], vec![]];
+        assert_eq!(
+            serialize_event(PUBKEY, 0, EventKind::METADATA, &tags, ""),
+            r#"[0,"79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",0,0,[["e","a\"b\\c\n"],[]],""]"#
+        );
+    }
+
+    // NIP-13の仕様に掲載されているイベント（内容はASCIIのみ）
+    #[test]
+    fn nip13_example_event() {
+        let event: Event = serde_json::from_str(
+            r#"{
+                "id": "000006d8c378af1779d2feebc7603a125d99eca0ccf1085959b307f64e5dd358",
+                "pubkey": "a48380f4cfcc1ad5378294fcac36439770f9c878dd880ffa94bb74ea54a6f243",
+                "created_at": 1651794653,
+                "kind": 1,
+                "tags": [["nonce", "776797", "20"]],
+                "content": "It's just me mining my own business",
+                "sig": "284622fc0a3f4f1303455d5175f7ba962a3300d136085b9566801bc2e0699de0c7e31e44c81fb40ad9049173742e904713c3594a1da0fc5d2382a25c11aba977"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            serialize_event(
+                &event.pubkey,
+                event.created_at,
+                event.kind,
+                &event.tags,
+                &event.content
+            ),
+            r#"[0,"a48380f4cfcc1ad5378294fcac36439770f9c878dd880ffa94bb74ea54a6f243",1651794653,1,[["nonce","776797","20"]],"It's just me mining my own business"]"#
+        );
+        let unsigned = UnsignedEvent::new(
+            event.pubkey.clone(),
+            event.kind,
+            event.tags.clone(),
+            event.content.clone(),
+            event.created_at,
+        );
+        assert_eq!(unsigned.id(), event.id);
+        assert!(event.verify().is_ok());
+    }
+}
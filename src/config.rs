@@ -0,0 +1,136 @@
+use std::{collections::HashSet, env, str::FromStr};
+
+use serde::de::DeserializeOwned;
+
+use crate::relay_info::{Fees, Retention};
+
+// イベントの保存先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreBackend {
+    Memory,
+    // SQLiteのデータベースファイルのパス
+    Sqlite(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    pub store: StoreBackend,
+    // イベントを受け付けない公開鍵のリスト
+    pub blocked_pubkeys: HashSet<String>,
+    // 1接続あたり1分間に受け付けるイベント数の上限（0の場合は無制限）
+    pub max_events_per_minute: u32,
+    // 現在時刻より何秒先のcreated_atまで許容するか
+    pub max_future_seconds: i64,
+    // 有効期限切れのイベントを削除する間隔（秒）
+    pub expiration_purge_interval: u64,
+    // NIP-13: 受け付けるイベントの最小の難易度（0の場合は制限なし）
+    pub min_pow_difficulty: u8,
+    // NIP-50: 全文検索用のインデックスを作成するかどうか
+    pub search_enabled: bool,
+    // NIP-42の認証で確認するリレーのURL（未設定の場合は接続時のHostヘッダーと照合する）
+    pub relay_url: Option<String>,
+    // REQやEVENTの前に認証を必須とするかどうか
+    pub auth_required: bool,
+    // 認証を必須とする場合に利用を許可する公開鍵のリスト（空の場合は全員を許可）
+    pub allowed_pubkeys: HashSet<String>,
+    // NIP-11のリレー情報ドキュメントに載せる情報
+    pub relay_name: Option<String>,
+    pub relay_description: Option<String>,
+    pub relay_pubkey: Option<String>,
+    pub relay_contact: Option<String>,
+    pub retention: Vec<Retention>,
+    pub fees: Option<Fees>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            store: StoreBackend::Sqlite("nostr.db".to_string()),
+            blocked_pubkeys: HashSet::new(),
+            max_events_per_minute: 120,
+            max_future_seconds: 15 * 60,
+            expiration_purge_interval: 60,
+            min_pow_difficulty: 0,
+            search_enabled: false,
+            relay_url: None,
+            auth_required: false,
+            allowed_pubkeys: HashSet::new(),
+            relay_name: None,
+            relay_description: None,
+            relay_pubkey: None,
+            relay_contact: None,
+            retention: Vec::new(),
+            fees: None,
+        }
+    }
+}
+
+impl RelayConfig {
+    // 環境変数（.envファイルを含む）から設定を読み込む
+    // 指定されていない項目はデフォルト値を使う
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let store = match env::var("NOSTR_STORE").as_deref() {
+            Ok("memory") => StoreBackend::Memory,
+            _ => env::var("NOSTR_DATABASE_PATH")
+                .map(StoreBackend::Sqlite)
+                .unwrap_or(default.store),
+        };
+        Self {
+            store,
+            blocked_pubkeys: env::var("NOSTR_BLOCKED_PUBKEYS")
+                .map(|v| split_list(&v).collect())
+                .unwrap_or(default.blocked_pubkeys),
+            max_events_per_minute: env_or(
+                "NOSTR_MAX_EVENTS_PER_MINUTE",
+                default.max_events_per_minute,
+            ),
+            max_future_seconds: env_or("NOSTR_MAX_FUTURE_SECONDS", default.max_future_seconds),
+            expiration_purge_interval: env_or(
+                "NOSTR_EXPIRATION_PURGE_INTERVAL",
+                default.expiration_purge_interval,
+            ),
+            min_pow_difficulty: env_or("NOSTR_MIN_POW_DIFFICULTY", default.min_pow_difficulty),
+            search_enabled: env_or("NOSTR_SEARCH_ENABLED", default.search_enabled),
+            relay_url: env::var("NOSTR_RELAY_URL").ok(),
+            auth_required: env_or("NOSTR_AUTH_REQUIRED", default.auth_required),
+            allowed_pubkeys: env::var("NOSTR_ALLOWED_PUBKEYS")
+                .map(|v| split_list(&v).collect())
+                .unwrap_or(default.allowed_pubkeys),
+            relay_name: env::var("NOSTR_RELAY_NAME").ok(),
+            relay_description: env::var("NOSTR_RELAY_DESCRIPTION").ok(),
+            relay_pubkey: env::var("NOSTR_RELAY_PUBKEY").ok(),
+            relay_contact: env::var("NOSTR_RELAY_CONTACT").ok(),
+            // 保存期間と料金体系はNIP-11と同じ形式のJSONで指定する
+            retention: env_json("NOSTR_RELAY_RETENTION").unwrap_or(default.retention),
+            fees: env_json("NOSTR_RELAY_FEES").or(default.fees),
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn env_json<T: DeserializeOwned>(key: &str) -> Option<T> {
+    let value = env::var(key).ok()?;
+    match serde_json::from_str(&value) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            tracing::error!("invalid {}: {}", key, e);
+            None
+        }
+    }
+}
+
+// カンマ区切りのリストを分割する
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+}
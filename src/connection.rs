@@ -0,0 +1,87 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use axum::extract::ws::Message;
+use rand::RngCore;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::message::ServerMessage;
+
+// NIP-42で認証済みの公開鍵の集合
+// 接続とその接続のサブスクライバーで共有し、REQの後に認証しても配信に反映されるようにする
+#[derive(Clone, Default)]
+pub struct AuthedPubkeys(Arc<RwLock<HashSet<String>>>);
+
+impl AuthedPubkeys {
+    pub fn contains(&self, pubkey: &str) -> bool {
+        self.0.read().unwrap().contains(pubkey)
+    }
+
+    pub fn to_vec(&self) -> Vec<String> {
+        self.0.read().unwrap().iter().cloned().collect()
+    }
+}
+
+// クライアントとの1つのWebSocket接続の状態
+pub struct Connection {
+    pub who: SocketAddr,
+    pub sender: UnboundedSender<Message>,
+    // NIP-42の認証に使う接続毎のチャレンジ文字列
+    pub challenge: String,
+    // 接続時のHostヘッダー。リレーのURLが設定されていない場合に認証イベントと照合する
+    pub host: Option<String>,
+    // NIP-42で認証済みの公開鍵のリスト
+    authed_pubkeys: AuthedPubkeys,
+}
+
+impl Connection {
+    pub fn new(who: SocketAddr, sender: UnboundedSender<Message>) -> Self {
+        let mut challenge = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        Self {
+            who,
+            sender,
+            challenge: hex::encode(challenge),
+            host: None,
+            authed_pubkeys: AuthedPubkeys::default(),
+        }
+    }
+
+    pub fn with_host(mut self, host: Option<String>) -> Self {
+        self.host = host;
+        self
+    }
+
+    // クライアントにメッセージを送信する
+    pub fn send(&self, message: &ServerMessage) {
+        let _ = self
+            .sender
+            .send(Message::Text(serde_json::to_string(message).unwrap()));
+    }
+
+    pub fn authenticate(&self, pubkey: String) {
+        self.authed_pubkeys.0.write().unwrap().insert(pubkey);
+    }
+
+    pub fn authed_pubkeys(&self) -> Vec<String> {
+        self.authed_pubkeys
+            .0
+            .read()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_authenticated_as(&self, pubkey: &str) -> bool {
+        self.authed_pubkeys.contains(pubkey)
+    }
+
+    // サブスクライバーに持たせる、認証状態を共有するハンドル
+    pub fn shared_authed_pubkeys(&self) -> AuthedPubkeys {
+        self.authed_pubkeys.clone()
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fmt, str::FromStr};
+
+use secp256k1::{schnorr::Signature, Keypair, Message, SecretKey, XOnlyPublicKey, SECP256K1};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::NostrError,
+    event::{Event, EventKind},
+};
+
+// NIP-26: 委任の条件
+// "kind="は複数指定した場合いずれかに一致すればよく、それ以外の条件はすべて満たす必要がある
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conditions {
+    pub kinds: Vec<EventKind>,
+    // created_at>の値
+    pub created_after: Option<i64>,
+    // created_at<の値
+    pub created_before: Option<i64>,
+}
+
+impl Conditions {
+    // イベントの種類と作成日時が条件を満たすかどうか
+    pub fn allows(&self, kind: EventKind, created_at: i64) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&kind))
+            && self.created_after.is_none_or(|after| created_at > after)
+            && self.created_before.is_none_or(|before| created_at < before)
+    }
+}
+
+impl FromStr for Conditions {
+    type Err = NostrError;
+
+    // "kind=1&created_at>1674834236&created_at<1677426236"の形式の文字列を読み取る
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conditions = Conditions::default();
+        for condition in s.split('&') {
+            let invalid =
+                || NostrError::InvalidDelegation(format!("invalid condition {condition}"));
+            if let Some(kind) = condition.strip_prefix("kind=") {
+                conditions
+                    .kinds
+                    .push(kind.parse::<u16>().map_err(|_| invalid())?.into());
+            } else if let Some(after) = condition.strip_prefix("created_at>") {
+                conditions.created_after = Some(after.parse().map_err(|_| invalid())?);
+            } else if let Some(before) = condition.strip_prefix("created_at<") {
+                conditions.created_before = Some(before.parse().map_err(|_| invalid())?);
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+impl fmt::Display for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let conditions: Vec<String> = self
+            .kinds
+            .iter()
+            .map(|kind| format!("kind={}", u16::from(*kind)))
+            .chain(self.created_after.map(|t| format!("created_at>{t}")))
+            .chain(self.created_before.map(|t| format!("created_at<{t}")))
+            .collect();
+        f.write_str(&conditions.join("&"))
+    }
+}
+
+// 委任者が署名する文字列のハッシュ
+fn delegation_digest(delegatee_pubkey: &str, conditions: &str) -> Message {
+    let token = format!("nostr:delegation:{delegatee_pubkey}:{conditions}");
+    Message::from_digest(Sha256::digest(token.as_bytes()).into())
+}
+
+// 委任者の秘密鍵で、委任先の公開鍵に条件付きで投稿を委任する"delegation"タグを作成する
+// 条件のない委任は検証時に受け付けないので作成しない
+#[allow(dead_code)]
+pub fn delegation_tag(
+    delegator_seckey: &str,
+    delegatee_pubkey: &str,
+    conditions: &Conditions,
+) -> Result<Vec<String>, NostrError> {
+    if *conditions == Conditions::default() {
+        return Err(NostrError::InvalidDelegation(
+            "delegation must have at least one condition".to_string(),
+        ));
+    }
+    let key = SecretKey::from_slice(&hex::decode(delegator_seckey)?)
+        .map_err(|e| NostrError::InvalidKey(e.to_string()))?;
+    let keypair = Keypair::from_secret_key(SECP256K1, &key);
+    let conditions = conditions.to_string();
+    let sig = SECP256K1.sign_schnorr(&delegation_digest(delegatee_pubkey, &conditions), &keypair);
+    Ok(vec![
+        "delegation".to_string(),
+        keypair.x_only_public_key().0.to_string(),
+        conditions,
+        hex::encode(sig.serialize()),
+    ])
+}
+
+// イベントの"delegation"タグを検証し、委任者の公開鍵を返す
+// タグがない場合はNone
+pub fn verify_delegation(event: &Event) -> Result<Option<&str>, NostrError> {
+    let Some(tag) = event
+        .tags
+        .iter()
+        .find(|t| t.first().is_some_and(|n| n == "delegation"))
+    else {
+        return Ok(None);
+    };
+    let [_, delegator, conditions, token] = tag.as_slice() else {
+        return Err(NostrError::InvalidDelegation(
+            "delegation tag must have delegator, conditions and token".to_string(),
+        ));
+    };
+
+    if !conditions
+        .parse::<Conditions>()?
+        .allows(event.kind, event.created_at)
+    {
+        return Err(NostrError::InvalidDelegation(
+            "event does not satisfy the delegation conditions".to_string(),
+        ));
+    }
+
+    let pubkey = XOnlyPublicKey::from_slice(&hex::decode(delegator)?)
+        .map_err(|e| NostrError::InvalidKey(e.to_string()))?;
+    let sig = Signature::from_slice(&hex::decode(token)?)
+        .map_err(|e| NostrError::InvalidSignature(e.to_string()))?;
+    SECP256K1
+        .verify_schnorr(&sig, &delegation_digest(&event.pubkey, conditions), &pubkey)
+        .map_err(|e| NostrError::InvalidSignature(e.to_string()))?;
+    Ok(Some(delegator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delegation_tag, verify_delegation, Conditions};
+    use crate::{
+        event::{Event, EventKind},
+        keys::Keys,
+    };
+
+    // NIP-26の例
+    const DELEGATOR_SECKEY: &str =
+        "ee35e8bb71131c02c1d7e73231daa48e9953d329a4b701f7133c8f46dd21139c";
+    const DELEGATOR_PUBKEY: &str =
+        "8e0d3d3eb2881ec137a11debe736a9086715a8c8beeeda615780064d68bc25dd";
+    const DELEGATEE_SECKEY: &str =
+        "777e4f60b4aa87937e13acc84f7abcc3c93cc035cb4c1e9f7a9086dd78fffce1";
+    const CONDITIONS: &str = "kind=1&created_at>1674834236&created_at<1677426236";
+    const TOKEN: &str = "6f44d7fe4f1c09f3954640fb58bd12bae8bb8ff4120853c4693106c82e920e2b898f1f9ba9bd65449a987c39c0423426ab7b53910c0c6abfb41b30bc16e5f524";
+
+    fn delegated_event(kind: EventKind, created_at: i64, tag: Vec<String>) -> Event {
+        Keys::parse(DELEGATEE_SECKEY)
+            .unwrap()
+            .sign_event(kind, vec![tag], "delegated".to_string(), created_at)
+            .unwrap()
+    }
+
+    fn spec_tag() -> Vec<String> {
+        ["delegation", DELEGATOR_PUBKEY, CONDITIONS, TOKEN]
+            .map(str::to_string)
+            .to_vec()
+    }
+
+    #[test]
+    fn parse_and_format_conditions() {
+        let conditions: Conditions = CONDITIONS.parse().unwrap();
+        assert_eq!(
+            conditions,
+            Conditions {
+                kinds: vec![EventKind::TEXT_NOTE],
+                created_after: Some(1674834236),
+                created_before: Some(1677426236),
+            }
+        );
+        assert_eq!(conditions.to_string(), CONDITIONS);
+        assert!("kind=1&pubkey=abc".parse::<Conditions>().is_err());
+    }
+
+    #[test]
+    fn verify_spec_token() {
+        let event = delegated_event(EventKind::TEXT_NOTE, 1675000000, spec_tag());
+        assert_eq!(verify_delegation(&event).unwrap(), Some(DELEGATOR_PUBKEY));
+    }
+
+    #[test]
+    fn reject_events_outside_conditions() {
+        let late = delegated_event(EventKind::TEXT_NOTE, 1677426236, spec_tag());
+        assert!(verify_delegation(&late).is_err());
+        let reaction = delegated_event(EventKind::REACTION, 1675000000, spec_tag());
+        assert!(verify_delegation(&reaction).is_err());
+    }
+
+    #[test]
+    fn build_and_verify_tag() {
+        let delegatee = Keys::parse(DELEGATEE_SECKEY).unwrap().public_key();
+        let conditions = Conditions {
+            kinds: vec![EventKind::TEXT_NOTE, EventKind::REACTION],
+            created_after: None,
+            created_before: Some(1700000000),
+        };
+        let tag = delegation_tag(DELEGATOR_SECKEY, &delegatee.to_string(), &conditions).unwrap();
+        assert_eq!(tag[1], DELEGATOR_PUBKEY);
+        assert_eq!(tag[2], "kind=1&kind=7&created_at<1700000000");
+
+        let event = delegated_event(EventKind::REACTION, 1690000000, tag.clone());
+        assert_eq!(verify_delegation(&event).unwrap(), Some(DELEGATOR_PUBKEY));
+
+        assert!(delegation_tag(DELEGATOR_SECKEY, &event.pubkey, &Conditions::default()).is_err());
+
+        // 別の委任者の公開鍵に差し替えたタグは検証に失敗する
+        let mut forged = tag;
+        forged[1] = event.pubkey.clone();
+        let event = delegated_event(EventKind::REACTION, 1690000000, forged);
+        assert!(verify_delegation(&event).is_err());
+    }
+}
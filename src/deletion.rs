@@ -0,0 +1,237 @@
+use crate::{
+    error::NostrError,
+    event::{Event, EventKind},
+    req::Filter,
+    store::EventStore,
+};
+
+// NIP-09の削除リクエスト(kind 5)で参照されたイベントのうち、同じpubkeyのものを削除する
+// 削除リクエスト自体は削除の対象にしない
+pub fn apply_deletion(store: &dyn EventStore, deletion: &Event) -> Result<usize, NostrError> {
+    let mut deleted = 0;
+
+    // "e"タグで参照されたイベント
+    let ids: Vec<String> = tag_values(deletion, "e").map(str::to_string).collect();
+    if !ids.is_empty() {
+        let targets: Vec<String> = store
+            .query_filter(
+                &Filter::new()
+                    .ids(ids)
+                    .authors(vec![deletion.pubkey.clone()]),
+            )?
+            .into_iter()
+            .filter(|e| e.kind != EventKind::DELETION)
+            .map(|e| e.id)
+            .collect();
+        if !targets.is_empty() {
+            deleted += store.delete(&Filter::new().ids(targets))?;
+        }
+    }
+
+    // "a"タグで参照されたaddressable(replaceable)イベントのうち、削除リクエスト以前のもの
+    for (kind, pubkey, d) in tag_values(deletion, "a").filter_map(parse_coordinate) {
+        // 通常のイベントはアドレスで参照できないので、"a"タグでは削除しない
+        if pubkey != deletion.pubkey || !(kind.is_replaceable() || kind.is_addressable()) {
+            continue;
+        }
+        let targets: Vec<String> = store
+            .query_filter(
+                &Filter::new()
+                    .kinds(vec![kind.into()])
+                    .authors(vec![pubkey.to_string()])
+                    .until(deletion.created_at),
+            )?
+            .into_iter()
+            .filter(|e| !kind.is_addressable() || e.d_tag() == d)
+            .map(|e| e.id)
+            .collect();
+        if !targets.is_empty() {
+            deleted += store.delete(&Filter::new().ids(targets))?;
+        }
+    }
+    Ok(deleted)
+}
+
+// イベントの作成者が既に削除リクエストを出しているかどうか
+// 削除されたイベントが再度投稿された場合に受け付けないために使う
+pub fn is_deleted(store: &dyn EventStore, event: &Event) -> Result<bool, NostrError> {
+    if event.kind == EventKind::DELETION {
+        return Ok(false);
+    }
+    let deletions = Filter::new()
+        .kinds(vec![EventKind::DELETION.into()])
+        .authors(vec![event.pubkey.clone()]);
+
+    if !store
+        .query_filter(&deletions.clone().e_tags(vec![event.id.clone()]).limit(1))?
+        .is_empty()
+    {
+        return Ok(true);
+    }
+
+    if event.kind.is_replaceable() || event.kind.is_addressable() {
+        let coordinate = format!(
+            "{}:{}:{}",
+            u16::from(event.kind),
+            event.pubkey,
+            if event.kind.is_addressable() {
+                event.d_tag()
+            } else {
+                ""
+            }
+        );
+        let deleted = !store
+            .query_filter(
+                &deletions
+                    .tag('a', vec![coordinate])
+                    .since(event.created_at)
+                    .limit(1),
+            )?
+            .is_empty();
+        return Ok(deleted);
+    }
+    Ok(false)
+}
+
+fn tag_values<'a>(event: &'a Event, name: &'a str) -> impl Iterator<Item = &'a str> {
+    event
+        .tags
+        .iter()
+        .filter(move |t| t.first().is_some_and(|n| n == name))
+        .filter_map(|t| t.get(1))
+        .map(String::as_str)
+}
+
+// "<kind>:<pubkey>:<d tag>"の形式のアドレスを分解する
+fn parse_coordinate(coordinate: &str) -> Option<(EventKind, &str, &str)> {
+    let mut parts = coordinate.splitn(3, ':');
+    let kind = parts.next()?.parse::<u16>().ok()?;
+    let pubkey = parts.next()?;
+    let d = parts.next().unwrap_or("");
+    Some((EventKind::from(kind), pubkey, d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_deletion, is_deleted};
+    use crate::{
+        delegation::{delegation_tag, Conditions},
+        event::{Event, EventKind},
+        req::Filter,
+        store::{EventStore, MemoryStore},
+        test_util::{alice, signed_event, ALICE, BOB},
+    };
+
+    fn save_all(store: &MemoryStore, events: &[&Event]) {
+        for e in events {
+            store.save(e).unwrap();
+        }
+    }
+
+    #[test]
+    fn delete_own_events_by_id() {
+        let store = MemoryStore::new();
+        let note = signed_event(ALICE, 1, vec![], "content", 100);
+        let kept = signed_event(ALICE, 1, vec![vec!["t", "kept"]], "content", 100);
+        let deletion = signed_event(ALICE, 5, vec![vec!["e", &note.id]], "content", 200);
+        save_all(&store, &[&note, &kept, &deletion]);
+
+        assert_eq!(apply_deletion(&store, &deletion).unwrap(), 1);
+        let events = store.query(&[Filter::new()]).unwrap();
+        assert_eq!(events, vec![deletion.clone(), kept]);
+        assert!(is_deleted(&store, &note).unwrap());
+    }
+
+    #[test]
+    fn ignore_cross_author_deletion() {
+        let store = MemoryStore::new();
+        let note = signed_event(ALICE, 1, vec![], "content", 100);
+        let article = signed_event(ALICE, 30023, vec![vec!["d", "article"]], "content", 100);
+        let coordinate = format!("30023:{}:article", article.pubkey);
+        let deletion = signed_event(
+            BOB,
+            5,
+            vec![vec!["e", &note.id], vec!["a", &coordinate]],
+            "content",
+            200,
+        );
+        save_all(&store, &[&note, &article, &deletion]);
+
+        assert_eq!(apply_deletion(&store, &deletion).unwrap(), 0);
+        assert_eq!(store.count(&[Filter::new()]).unwrap(), 3);
+        assert!(!is_deleted(&store, &note).unwrap());
+        assert!(!is_deleted(&store, &article).unwrap());
+    }
+
+    #[test]
+    fn delete_addressable_events_by_coordinate() {
+        let store = MemoryStore::new();
+        let article = signed_event(ALICE, 30023, vec![vec!["d", "article"]], "content", 100);
+        let other = signed_event(ALICE, 30023, vec![vec!["d", "other"]], "content", 100);
+        let coordinate = format!("30023:{}:article", article.pubkey);
+        let deletion = signed_event(ALICE, 5, vec![vec!["a", &coordinate]], "content", 200);
+        save_all(&store, &[&article, &other, &deletion]);
+
+        assert_eq!(apply_deletion(&store, &deletion).unwrap(), 1);
+        assert!(is_deleted(&store, &article).unwrap());
+        // 削除リクエストより新しいバージョンは削除されていない
+        let newer = signed_event(ALICE, 30023, vec![vec!["d", "article"]], "content", 300);
+        assert!(!is_deleted(&store, &newer).unwrap());
+        assert!(!is_deleted(&store, &other).unwrap());
+    }
+
+    #[test]
+    fn deletion_events_are_not_deleted() {
+        let store = MemoryStore::new();
+        let first = signed_event(ALICE, 5, vec![], "content", 100);
+        let second = signed_event(ALICE, 5, vec![vec!["e", &first.id]], "content", 200);
+        save_all(&store, &[&first, &second]);
+
+        assert_eq!(apply_deletion(&store, &second).unwrap(), 0);
+        assert_eq!(store.count(&[Filter::new()]).unwrap(), 2);
+    }
+
+    #[test]
+    fn ignore_coordinates_of_regular_kinds() {
+        let store = MemoryStore::new();
+        let notes: Vec<Event> = (0..3)
+            .map(|i| signed_event(ALICE, 1, vec![], "content", 100 + i))
+            .collect();
+        let coordinate = format!("1:{}:", notes[0].pubkey);
+        let deletion = signed_event(ALICE, 5, vec![vec!["a", &coordinate]], "content", 200);
+        save_all(&store, &notes.iter().chain([&deletion]).collect::<Vec<_>>());
+
+        assert_eq!(apply_deletion(&store, &deletion).unwrap(), 0);
+        assert_eq!(store.count(&[Filter::new()]).unwrap(), 4);
+        for note in &notes {
+            assert!(!is_deleted(&store, note).unwrap());
+        }
+    }
+
+    #[test]
+    fn delegatee_cannot_delete_delegator_events() {
+        let store = MemoryStore::new();
+        let note = signed_event(BOB, 1, vec![], "content", 100);
+        let conditions = Conditions {
+            kinds: vec![EventKind::DELETION],
+            ..Default::default()
+        };
+        let alice = alice().public_key();
+        let delegation = delegation_tag(BOB, &alice, &conditions).unwrap();
+        let deletion = signed_event(
+            ALICE,
+            5,
+            vec![
+                delegation.iter().map(String::as_str).collect(),
+                vec!["e", &note.id],
+            ],
+            "content",
+            200,
+        );
+        save_all(&store, &[&note, &deletion]);
+
+        // 削除の判定も実際の削除も、委任者ではなく署名者の公開鍵で行う
+        assert_eq!(apply_deletion(&store, &deletion).unwrap(), 0);
+        assert!(!is_deleted(&store, &note).unwrap());
+    }
+}
@@ -4,4 +4,27 @@ use thiserror::Error;
 pub enum NostrError {
     #[error("無効な形式のメッセージ: {0}")]
     InvalidMessage(String),
+    #[error("無効な16進数文字列: {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+    #[error("無効な鍵: {0}")]
+    InvalidKey(String),
+    #[error("イベントIDが内容と一致しない")]
+    IdMismatch,
+    #[error("無効な署名: {0}")]
+    InvalidSignature(String),
+    #[error("無効なbech32文字列: {0}")]
+    InvalidBech32(String),
+    #[error("無効な委任: {0}")]
+    InvalidDelegation(String),
+    #[allow(dead_code)]
+    #[error("暗号化・復号のエラー: {0}")]
+    Crypto(String),
+    #[error("ストレージのエラー: {0}")]
+    Storage(String),
+}
+
+impl From<rusqlite::Error> for NostrError {
+    fn from(e: rusqlite::Error) -> Self {
+        NostrError::Storage(e.to_string())
+    }
 }
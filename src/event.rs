@@ -1,9 +1,14 @@
-use hex;
-use libsecp256k1::{sign, Message, SecretKey};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use secp256k1::{schnorr::Signature, Message, XOnlyPublicKey, SECP256K1};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+use crate::{canonical, error::NostrError, keys::Keys, pow};
+
+// NIP-59のrumorとしてidを含めたままJSONにできる
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct UnsignedEvent {
     // SHA-256 (32バイト) を小文字の16進数で表記
     id: String,
@@ -28,20 +33,7 @@ impl UnsignedEvent {
         content: String,
         created_at: i64,
     ) -> Self {
-        // シリアライズしたイベントからハッシュ値(id)を計算
-        let serialized_event = format!(
-            r#"[0,"{}",{},{},{},"{}"]"#,
-            pubkey,
-            created_at,
-            u16::from(kind),
-            serde_json::to_string(&tags).unwrap(),
-            content
-        );
-
-        let mut hasher = Sha256::new();
-        hasher.update(serialized_event);
-        let hash = hasher.finalize();
-        let id = hex::encode(&hash);
+        let id = compute_id(&pubkey, created_at, kind, &tags, &content);
 
         Self {
             id,
@@ -54,15 +46,84 @@ impl UnsignedEvent {
     }
 
     #[allow(dead_code)]
-    pub fn sign(self, seckey: &str) -> Event {
-        // 計算したidと秘密鍵を使って署名を作成
-        let key = SecretKey::parse_slice(&hex::decode(seckey).unwrap()).unwrap();
-        let (signature, _) = sign(
-            &Message::parse_slice(&hex::decode(&self.id).unwrap()).unwrap(),
-            &key,
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[allow(dead_code)]
+    pub fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+
+    #[allow(dead_code)]
+    pub fn created_at(&self) -> i64 {
+        self.created_at
+    }
+
+    #[allow(dead_code)]
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    #[allow(dead_code)]
+    pub fn tags(&self) -> &[Vec<String>] {
+        &self.tags
+    }
+
+    #[allow(dead_code)]
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    // 外部から受け取った署名のないイベントのidが内容と一致することを検証する
+    #[allow(dead_code)]
+    pub fn verify_id(&self) -> Result<(), NostrError> {
+        let id = compute_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
         );
-        let sig = hex::encode(signature.serialize());
-        Event {
+        if id != self.id {
+            return Err(NostrError::IdMismatch);
+        }
+        Ok(())
+    }
+
+    // NIP-13: 指定した難易度を満たすまで"nonce"タグを変えながらIDを計算し直す
+    // threadsの数のスレッドで並列に探索する
+    #[allow(dead_code)]
+    pub fn mine(self, target: u32, threads: usize) -> Result<Self, NostrError> {
+        let (tags, id) = pow::mine(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+            target,
+            threads,
+        )?;
+        Ok(Self { id, tags, ..self })
+    }
+
+    #[allow(dead_code)]
+    pub fn sign(self, seckey: &str) -> Result<Event, NostrError> {
+        self.sign_with_keys(&Keys::parse(seckey)?)
+    }
+
+    // 計算したidと秘密鍵を使ってBIP-340のSchnorr署名を作成
+    // pubkeyが秘密鍵から導出した公開鍵と異なる場合は署名しない
+    pub fn sign_with_keys(self, keys: &Keys) -> Result<Event, NostrError> {
+        if self.pubkey != keys.public_key() {
+            return Err(NostrError::InvalidKey(
+                "pubkey does not match the secret key".to_string(),
+            ));
+        }
+        let message = Message::from_digest_slice(&hex::decode(&self.id)?)
+            .map_err(|_| NostrError::IdMismatch)?;
+        let sig = hex::encode(SECP256K1.sign_schnorr(&message, keys.keypair()).serialize());
+        Ok(Event {
             id: self.id,
             pubkey: self.pubkey,
             created_at: self.created_at,
@@ -70,11 +131,27 @@ impl UnsignedEvent {
             tags: self.tags,
             content: self.content,
             sig,
-        }
+        })
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+// NIP-01の形式でシリアライズしたイベントからハッシュ値(id)を計算
+pub fn compute_id(
+    pubkey: &str,
+    created_at: i64,
+    kind: EventKind,
+    tags: &[Vec<String>],
+    content: &str,
+) -> String {
+    let serialized_event = canonical::serialize_event(pubkey, created_at, kind, tags, content);
+
+    let mut hasher = Sha256::new();
+    hasher.update(serialized_event);
+    let hash = hasher.finalize();
+    hex::encode(hash)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Event {
     // SHA-256 (32バイト) を小文字の16進数で表記
     pub id: String,
@@ -92,28 +169,130 @@ pub struct Event {
     pub sig: String,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum EventKind {
-    MetaData,
-    TextNote,
+impl Event {
+    // 指定した名前の最初のタグの最初の値
+    pub fn tag_value(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|t| t.first().is_some_and(|n| n == name))
+            .and_then(|t| t.get(1))
+            .map(String::as_str)
+    }
+
+    // NIP-40の"expiration"タグで指定された有効期限（UNIXタイムスタンプ）
+    pub fn expiration(&self) -> Option<i64> {
+        self.tag_value("expiration").and_then(|v| v.parse().ok())
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expiration()
+            .is_some_and(|expiration| expiration <= now)
+    }
+
+    // NIP-26の"delegation"タグで指定された委任者の公開鍵
+    // 委任の検証はリレーがイベントを受け付ける際に行う
+    pub fn delegator(&self) -> Option<&str> {
+        self.tag_value("delegation")
+    }
+
+    // NIP-70の["-"]タグを持つ、作成者本人しか公開できないイベントかどうか
+    pub fn is_protected(&self) -> bool {
+        self.tags
+            .iter()
+            .any(|t| t.first().is_some_and(|n| n == "-"))
+    }
+
+    // addressableイベントの識別子（"d"タグの値、ない場合は空文字列）
+    pub fn d_tag(&self) -> &str {
+        self.tag_value("d").unwrap_or("")
+    }
+
+    // idがイベントの内容から計算した値と一致し、sigがpubkeyによる正しい署名であることを検証する
+    #[allow(dead_code)]
+    pub fn verify(&self) -> Result<(), NostrError> {
+        let id = compute_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        if id != self.id {
+            return Err(NostrError::IdMismatch);
+        }
+
+        let pubkey = XOnlyPublicKey::from_slice(&hex::decode(&self.pubkey)?)
+            .map_err(|e| NostrError::InvalidKey(e.to_string()))?;
+        let sig = Signature::from_slice(&hex::decode(&self.sig)?)
+            .map_err(|e| NostrError::InvalidSignature(e.to_string()))?;
+        let message = Message::from_digest_slice(&hex::decode(&self.id)?)
+            .map_err(|_| NostrError::IdMismatch)?;
+        SECP256K1
+            .verify_schnorr(&sig, &message, &pubkey)
+            .map_err(|e| NostrError::InvalidSignature(e.to_string()))
+    }
+}
+
+// 現在のUNIXタイムスタンプ（秒単位）
+pub fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// イベントの種類
+// 未知の種類も含めてすべてのu16の値をそのまま保持する
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct EventKind(u16);
+
+#[allow(dead_code)]
+impl EventKind {
+    pub const METADATA: EventKind = EventKind(0);
+    pub const TEXT_NOTE: EventKind = EventKind(1);
+    pub const CONTACT_LIST: EventKind = EventKind(3);
+    pub const ENCRYPTED_DIRECT_MESSAGE: EventKind = EventKind(4);
+    pub const DELETION: EventKind = EventKind(5);
+    pub const REPOST: EventKind = EventKind(6);
+    pub const REACTION: EventKind = EventKind(7);
+    pub const SEAL: EventKind = EventKind(13);
+    pub const CHAT_MESSAGE: EventKind = EventKind(14);
+    pub const FILE_MESSAGE: EventKind = EventKind(15);
+    pub const GIFT_WRAP: EventKind = EventKind(1059);
+    pub const DM_RELAY_LIST: EventKind = EventKind(10050);
+    pub const CLIENT_AUTH: EventKind = EventKind(22242);
+
+    // NIP-01の区分に従い、replaceable・ephemeral・addressableのいずれでもない種類
+    // 区分が定められていない範囲の種類も通常のイベントとして保存する
+    pub fn is_regular(&self) -> bool {
+        !self.is_replaceable() && !self.is_ephemeral() && !self.is_addressable()
+    }
+
+    // pubkeyとkindの組み合わせ毎に最新のイベントのみを保存する種類
+    pub fn is_replaceable(&self) -> bool {
+        matches!(self.0, 0 | 3 | 10000..=19999)
+    }
+
+    // 保存せずに接続中のサブスクライバーにのみ配信する種類
+    pub fn is_ephemeral(&self) -> bool {
+        matches!(self.0, 20000..=29999)
+    }
+
+    // pubkey、kind、"d"タグの組み合わせ毎に最新のイベントのみを保存する種類
+    pub fn is_addressable(&self) -> bool {
+        matches!(self.0, 30000..=39999)
+    }
 }
 
 impl From<EventKind> for u16 {
     fn from(kind: EventKind) -> u16 {
-        match kind {
-            EventKind::MetaData => 0,
-            EventKind::TextNote => 1,
-        }
+        kind.0
     }
 }
 
 impl From<u16> for EventKind {
     fn from(kind: u16) -> EventKind {
-        match kind {
-            0 => EventKind::MetaData,
-            1 => EventKind::TextNote,
-            _ => panic!("unknown event kind"),
-        }
+        EventKind(kind)
     }
 }
 
@@ -135,3 +314,94 @@ impl<'de> Deserialize<'de> for EventKind {
         Ok(kind.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{EventKind, UnsignedEvent};
+    use crate::{
+        error::NostrError,
+        test_util::{alice, ALICE},
+    };
+
+    fn signed_event() -> super::Event {
+        // UnsignedEvent::signそのものを確認するため、Keys::sign_eventは使わない
+        UnsignedEvent::new(
+            alice().public_key(),
+            EventKind::TEXT_NOTE,
+            vec![vec!["tag".to_string()]],
+            "content".to_string(),
+            1708838939,
+        )
+        .sign(ALICE)
+        .unwrap()
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let event = signed_event();
+        assert_eq!(event.sig.len(), 128);
+        assert!(event.verify().is_ok());
+    }
+
+    #[test]
+    fn verify_tampered_content() {
+        let mut event = signed_event();
+        event.content = "tampered".to_string();
+        assert!(matches!(event.verify(), Err(NostrError::IdMismatch)));
+    }
+
+    #[test]
+    fn verify_wrong_signature() {
+        let mut event = signed_event();
+        event.sig = "00".repeat(64);
+        assert!(matches!(
+            event.verify(),
+            Err(NostrError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn deserialize_unknown_kind() {
+        let kind: EventKind = serde_json::from_str("7").unwrap();
+        assert_eq!(kind, EventKind::REACTION);
+        let kind: EventKind = serde_json::from_str("65535").unwrap();
+        assert_eq!(u16::from(kind), 65535);
+        assert_eq!(serde_json::to_string(&kind).unwrap(), "65535");
+    }
+
+    #[test]
+    fn classify_kinds() {
+        for (kind, regular, replaceable, ephemeral, addressable) in [
+            (0, false, true, false, false),
+            (1, true, false, false, false),
+            (3, false, true, false, false),
+            (7, true, false, false, false),
+            (1000, true, false, false, false),
+            (10002, false, true, false, false),
+            (19999, false, true, false, false),
+            (20000, false, false, true, false),
+            (29999, false, false, true, false),
+            (30023, false, false, false, true),
+            (39999, false, false, false, true),
+            (40000, true, false, false, false),
+        ] {
+            let kind = EventKind::from(kind);
+            assert_eq!(kind.is_regular(), regular, "{kind:?}");
+            assert_eq!(kind.is_replaceable(), replaceable, "{kind:?}");
+            assert_eq!(kind.is_ephemeral(), ephemeral, "{kind:?}");
+            assert_eq!(kind.is_addressable(), addressable, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn sign_with_invalid_seckey() {
+        let event = UnsignedEvent::new(
+            "00".repeat(32),
+            EventKind::TEXT_NOTE,
+            vec![],
+            "content".to_string(),
+            1708838939,
+        );
+        assert!(matches!(event.sign("zz"), Err(NostrError::InvalidHex(_))));
+    }
+}
@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use crate::{
+    event::Event,
+    message::{ServerMessage, ServerMessageEvent},
+    subscriber::Subscriber,
+};
+
+// イベントに合致するサブスクリプションそれぞれのチャネルに
+// サブスクリプションIDを付けたEVENTメッセージを配信し、配信した数を返す
+// 複数のフィルタが合致しても1つのサブスクリプションには1度だけ配信する
+pub fn fanout(event: &Event, subscribers: &HashMap<String, Vec<Subscriber>>) -> usize {
+    let mut delivered = 0;
+    for s in subscribers.values().flatten() {
+        if !s.matches(event) {
+            continue;
+        }
+        let message = ServerMessage::Event(ServerMessageEvent {
+            subscribe_id: s.id.clone(),
+            event: event.clone(),
+        });
+        if s.send(&message) {
+            delivered += 1;
+        }
+    }
+    delivered
+}
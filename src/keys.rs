@@ -0,0 +1,164 @@
+use std::fmt;
+
+use secp256k1::{ecdh, Keypair, Parity, PublicKey, SecretKey, XOnlyPublicKey, SECP256K1};
+use zeroize::Zeroizing;
+
+use crate::{
+    error::NostrError,
+    event::{Event, EventKind, UnsignedEvent},
+    nip19,
+};
+
+// 秘密鍵と、そこから導出したx-only公開鍵の組
+// 破棄する際に秘密鍵をメモリから消去する
+pub struct Keys {
+    keypair: Keypair,
+}
+
+#[allow(dead_code)]
+impl Keys {
+    // 新しい秘密鍵をランダムに生成する
+    pub fn generate() -> Self {
+        Self {
+            keypair: Keypair::new(SECP256K1, &mut rand::thread_rng()),
+        }
+    }
+
+    // 16進数もしくはnsec形式の秘密鍵を読み取る
+    pub fn parse(secret: &str) -> Result<Self, NostrError> {
+        let secret = if secret.starts_with("nsec1") {
+            Zeroizing::new(nip19::decode_nsec(secret)?)
+        } else {
+            Zeroizing::new(secret.to_string())
+        };
+        let bytes = Zeroizing::new(hex::decode(secret.as_str())?);
+        let mut key =
+            SecretKey::from_slice(&bytes).map_err(|e| NostrError::InvalidKey(e.to_string()))?;
+        let keys = Self {
+            keypair: Keypair::from_secret_key(SECP256K1, &key),
+        };
+        key.non_secure_erase();
+        Ok(keys)
+    }
+
+    // 公開鍵 (32バイト) を小文字の16進数で表記
+    pub fn public_key(&self) -> String {
+        self.x_only_public_key().to_string()
+    }
+
+    pub fn x_only_public_key(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+
+    pub fn npub(&self) -> Result<String, NostrError> {
+        nip19::encode_npub(&self.public_key())
+    }
+
+    // 秘密鍵のnsec形式。不要になったら消去されるようにZeroizingで包んで返す
+    pub fn nsec(&self) -> Result<Zeroizing<String>, NostrError> {
+        let secret = Zeroizing::new(hex::encode(self.keypair.secret_bytes()));
+        Ok(Zeroizing::new(nip19::encode_nsec(&secret)?))
+    }
+
+    pub(crate) fn keypair(&self) -> &Keypair {
+        &self.keypair
+    }
+
+    // 相手の公開鍵とのECDHで得られる共有点のx座標（ハッシュ化しない）
+    // NIP-04・NIP-44の暗号化の鍵の元になる
+    pub fn shared_secret(&self, public_key: &str) -> Result<Zeroizing<[u8; 32]>, NostrError> {
+        let public_key = XOnlyPublicKey::from_slice(&hex::decode(public_key)?)
+            .map_err(|e| NostrError::InvalidKey(e.to_string()))?;
+        let point = Zeroizing::new(ecdh::shared_secret_point(
+            &PublicKey::from_x_only_public_key(public_key, Parity::Even),
+            &self.keypair.secret_key(),
+        ));
+        let mut x = Zeroizing::new([0u8; 32]);
+        x.copy_from_slice(&point[..32]);
+        Ok(x)
+    }
+
+    // この鍵の公開鍵でイベントを作成して署名する
+    pub fn sign_event(
+        &self,
+        kind: EventKind,
+        tags: Vec<Vec<String>>,
+        content: String,
+        created_at: i64,
+    ) -> Result<Event, NostrError> {
+        UnsignedEvent::new(self.public_key(), kind, tags, content, created_at).sign_with_keys(self)
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        self.keypair.non_secure_erase();
+    }
+}
+
+// 秘密鍵はログなどに出力しない
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keys;
+    use crate::event::{EventKind, UnsignedEvent};
+
+    // NIP-19の例
+    const NSEC: &str = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+    const SECKEY: &str = "67dea2ed018072d675f5415ecfaed7d2597555e202d85b3d65ea4e58d2d92ffa";
+
+    #[test]
+    fn parse_hex_and_nsec() {
+        let from_hex = Keys::parse(SECKEY).unwrap();
+        let from_nsec = Keys::parse(NSEC).unwrap();
+        assert_eq!(from_hex.public_key(), from_nsec.public_key());
+        assert_eq!(from_hex.nsec().unwrap().as_str(), NSEC);
+        assert!(from_hex.npub().unwrap().starts_with("npub1"));
+
+        assert!(Keys::parse("zz").is_err());
+        assert!(Keys::parse(&"0".repeat(64)).is_err());
+        let npub = from_hex.npub().unwrap();
+        assert!(Keys::parse(&npub).is_err());
+    }
+
+    #[test]
+    fn generate_distinct_keys() {
+        let a = Keys::generate();
+        let b = Keys::generate();
+        assert_ne!(a.public_key(), b.public_key());
+        assert!(!format!("{:?}", a).contains(&hex::encode(a.keypair.secret_bytes())));
+    }
+
+    #[test]
+    fn sign_event_with_own_pubkey() {
+        let keys = Keys::generate();
+        let event = keys
+            .sign_event(
+                EventKind::TEXT_NOTE,
+                vec![],
+                "content".to_string(),
+                1700000000,
+            )
+            .unwrap();
+        assert_eq!(event.pubkey, keys.public_key());
+        assert!(event.verify().is_ok());
+
+        // 別の公開鍵で作成したイベントには署名しない
+        let other = Keys::generate();
+        let unsigned = UnsignedEvent::new(
+            other.public_key(),
+            EventKind::TEXT_NOTE,
+            vec![],
+            "content".to_string(),
+            1700000000,
+        );
+        assert!(unsigned.sign_with_keys(&keys).is_err());
+    }
+}
@@ -1,15 +1,37 @@
+mod auth;
+mod canonical;
+mod config;
+mod connection;
+mod delegation;
+mod deletion;
 mod error;
 mod event;
+mod fanout;
+mod keys;
 mod message;
+mod nip04;
+mod nip17;
+mod nip19;
+mod nip44;
+mod nip59;
+mod pow;
+mod relay_info;
 mod req;
+mod search;
 mod server;
+mod store;
 mod subscriber;
+#[cfg(test)]
+mod test_util;
+mod validation;
 
+use config::RelayConfig;
 use server::serve;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() {
+    dotenvy::dotenv().ok();
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -17,5 +39,5 @@ async fn main() {
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
-    serve().await;
+    serve(RelayConfig::from_env()).await;
 }
@@ -16,6 +16,8 @@ pub enum ClientMessage {
     Req(Req),
     Event(Event),
     Close(String),
+    Auth(Event),
+    Count(Req),
 }
 
 impl Serialize for ClientMessage {
@@ -27,6 +29,8 @@ impl Serialize for ClientMessage {
             ClientMessage::Req(req) => serialize_req(req, serializer),
             ClientMessage::Event(event) => serialize_event(event, serializer),
             ClientMessage::Close(id) => serialize_close(id, serializer),
+            ClientMessage::Auth(event) => serialize_auth_event(event, serializer),
+            ClientMessage::Count(req) => serialize_count(req, serializer),
         }
     }
 }
@@ -46,6 +50,21 @@ where
     seq.end()
 }
 
+fn serialize_count<S>(req: &Req, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let length = 2 + req.filter.len();
+    let mut seq = serializer.serialize_seq(Some(length))?;
+    seq.serialize_element("COUNT")?;
+    seq.serialize_element(req.id.as_str())?;
+    for filter in &req.filter {
+        seq.serialize_element(filter)?;
+    }
+
+    seq.end()
+}
+
 fn serialize_event<S>(event: &Event, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -66,6 +85,16 @@ where
     seq.end()
 }
 
+fn serialize_auth_event<S>(event: &Event, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(2))?;
+    seq.serialize_element("AUTH")?;
+    seq.serialize_element(event)?;
+    seq.end()
+}
+
 impl<'de> Deserialize<'de> for ClientMessage {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -95,6 +124,8 @@ impl<'de> Visitor<'de> for ClientMessageVisitor {
             "REQ" => deserialize_req(&self, &mut seq),
             "EVENT" => deserialize_event(&self, &mut seq),
             "CLOSE" => deserialize_close(&self, &mut seq),
+            "AUTH" => deserialize_auth_event(&self, &mut seq),
+            "COUNT" => deserialize_count(&self, &mut seq),
             _ => Err(de::Error::custom("unknown message kind")),
         }
     }
@@ -120,6 +151,26 @@ where
     }))
 }
 
+fn deserialize_count<'de, 'a, V>(
+    visitor: &'a ClientMessageVisitor,
+    seq: &mut V,
+) -> Result<ClientMessage, <V as SeqAccess<'de>>::Error>
+where
+    V: SeqAccess<'de>,
+{
+    let id = seq
+        .next_element::<String>()?
+        .ok_or_else(|| de::Error::invalid_length(1, visitor))?;
+    let mut filters = Vec::new();
+    while let Some(filter) = seq.next_element::<Filter>()? {
+        filters.push(filter);
+    }
+    Ok(ClientMessage::Count(Req {
+        id,
+        filter: filters,
+    }))
+}
+
 fn deserialize_event<'de, 'a, V>(
     visitor: &'a ClientMessageVisitor,
     seq: &mut V,
@@ -146,6 +197,19 @@ where
     Ok(ClientMessage::Close(id))
 }
 
+fn deserialize_auth_event<'de, 'a, V>(
+    visitor: &'a ClientMessageVisitor,
+    seq: &mut V,
+) -> Result<ClientMessage, <V as SeqAccess<'de>>::Error>
+where
+    V: SeqAccess<'de>,
+{
+    let event = seq
+        .next_element::<Event>()?
+        .ok_or_else(|| de::Error::invalid_length(1, visitor))?;
+    Ok(ClientMessage::Auth(event))
+}
+
 impl From<Req> for ClientMessage {
     fn from(req: Req) -> Self {
         ClientMessage::Req(req)
@@ -158,6 +222,7 @@ impl From<Event> for ClientMessage {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq)]
 pub enum ServerMessage {
     Event(ServerMessageEvent),
@@ -165,6 +230,8 @@ pub enum ServerMessage {
     EOSE(String),
     Closed(Closed),
     Notice(String),
+    Auth(String),
+    Count(ServerCount),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -186,6 +253,69 @@ pub struct Closed {
     pub message: String,
 }
 
+// COUNTの結果
+// approximateがfalseの場合は省略する
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerCount {
+    #[serde(skip)]
+    pub subscribe_id: String,
+    pub count: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate: bool,
+}
+
+impl ServerOk {
+    pub fn accepted(event_id: String) -> Self {
+        Self {
+            event_id,
+            accepted: true,
+            message: "".to_string(),
+        }
+    }
+
+    pub fn rejected(event_id: String, prefix: ReasonPrefix, message: &str) -> Self {
+        Self {
+            event_id,
+            accepted: false,
+            message: prefix.with_message(message),
+        }
+    }
+}
+
+// OKやCLOSEDメッセージの理由の先頭に付ける機械可読なプレフィックス
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReasonPrefix {
+    Duplicate,
+    Pow,
+    Blocked,
+    RateLimited,
+    Invalid,
+    Error,
+    AuthRequired,
+    Restricted,
+}
+
+impl ReasonPrefix {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasonPrefix::Duplicate => "duplicate",
+            ReasonPrefix::Pow => "pow",
+            ReasonPrefix::Blocked => "blocked",
+            ReasonPrefix::RateLimited => "rate-limited",
+            ReasonPrefix::Invalid => "invalid",
+            ReasonPrefix::Error => "error",
+            ReasonPrefix::AuthRequired => "auth-required",
+            ReasonPrefix::Restricted => "restricted",
+        }
+    }
+
+    // "<prefix>: <message>"の形式の理由を作成する
+    pub fn with_message(&self, message: &str) -> String {
+        format!("{}: {}", self.as_str(), message)
+    }
+}
+
 impl Serialize for ServerMessage {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -197,6 +327,8 @@ impl Serialize for ServerMessage {
             ServerMessage::EOSE(id) => serialize_eose(id, serializer),
             ServerMessage::Closed(closed) => serialize_closed(closed, serializer),
             ServerMessage::Notice(message) => serialize_notice(message, serializer),
+            ServerMessage::Auth(challenge) => serialize_auth_challenge(challenge, serializer),
+            ServerMessage::Count(count) => serialize_server_count(count, serializer),
         }
     }
 }
@@ -255,6 +387,27 @@ where
     seq.end()
 }
 
+fn serialize_auth_challenge<S>(challenge: &String, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(2))?;
+    seq.serialize_element("AUTH")?;
+    seq.serialize_element(challenge)?;
+    seq.end()
+}
+
+fn serialize_server_count<S>(count: &ServerCount, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(3))?;
+    seq.serialize_element("COUNT")?;
+    seq.serialize_element(&count.subscribe_id)?;
+    seq.serialize_element(count)?;
+    seq.end()
+}
+
 impl<'de> Deserialize<'de> for ServerMessage {
     fn deserialize<D>(deserializer: D) -> Result<ServerMessage, D::Error>
     where
@@ -286,6 +439,8 @@ impl<'de> Visitor<'de> for ServerMessageVisitor {
             "EOSE" => deserialize_eose(&self, &mut seq),
             "CLOSED" => deserialize_closed(&self, &mut seq),
             "NOTICE" => deserialize_notice(&self, &mut seq),
+            "AUTH" => deserialize_auth_challenge(&self, &mut seq),
+            "COUNT" => deserialize_server_count(&self, &mut seq),
             _ => Err(de::Error::custom("unknown message kind")),
         }
     }
@@ -378,17 +533,47 @@ where
     Ok(ServerMessage::Notice(message))
 }
 
+fn deserialize_auth_challenge<'de, 'a, V>(
+    visitor: &'a ServerMessageVisitor,
+    seq: &mut V,
+) -> Result<ServerMessage, <V as SeqAccess<'de>>::Error>
+where
+    V: SeqAccess<'de>,
+{
+    let challenge = seq
+        .next_element::<String>()?
+        .ok_or_else(|| de::Error::invalid_length(1, visitor))?;
+    Ok(ServerMessage::Auth(challenge))
+}
+
+fn deserialize_server_count<'de, 'a, V>(
+    visitor: &'a ServerMessageVisitor,
+    seq: &mut V,
+) -> Result<ServerMessage, <V as SeqAccess<'de>>::Error>
+where
+    V: SeqAccess<'de>,
+{
+    let subscribe_id = seq
+        .next_element::<String>()?
+        .ok_or_else(|| de::Error::invalid_length(1, visitor))?;
+    let count = seq
+        .next_element::<ServerCount>()?
+        .ok_or_else(|| de::Error::invalid_length(2, visitor))?;
+    Ok(ServerMessage::Count(ServerCount {
+        subscribe_id,
+        ..count
+    }))
+}
+
 #[cfg(test)]
 mod tests {
 
-    use crate::{message::Event, req::Filter};
-    use bech32::decode;
+    use crate::{keys::Keys, message::Event, req::Filter};
 
-    use crate::event::{EventKind, UnsignedEvent};
+    use crate::event::EventKind;
 
-    use super::{ClientMessage, ServerMessage, ServerMessageEvent};
+    use super::{ClientMessage, ServerCount, ServerMessage, ServerMessageEvent};
 
-    const TEST_PUBKEY: &str = "npub1test2s5u9l0z8dakmap5s6ddw8fvjsp6820h52nzjc35j8j8wv6qcnjx5q";
     const TEST_SECKEY: &str = "nsec1kj0mc49wzr2lqjka0m06ft0ku8n4zntgk6yh78vuvqdw7mnctk6q3uh0fr";
 
     fn data_provider_req<'a>() -> (ClientMessage, &'a str) {
@@ -427,26 +612,26 @@ mod tests {
     #[test]
     fn deserialize_req() {
         let (expected, serialized) = data_provider_req();
-        let message: ClientMessage = serde_json::from_str(&serialized).unwrap();
+        let message: ClientMessage = serde_json::from_str(serialized).unwrap();
         assert_eq!(message, expected);
     }
 
-    fn data_provider_event<'a>() -> (Event, String) {
+    fn data_provider_event() -> (Event, String) {
         let created_at = 1708838939;
-        let (_, pubkey) = decode(TEST_PUBKEY).unwrap();
-        let pubkey = hex::encode(pubkey);
-        let (_, seckey) = decode(TEST_SECKEY).unwrap();
-        let seckey = hex::encode(seckey);
-        let event = UnsignedEvent::new(
-            pubkey.clone(),
-            EventKind::TextNote,
-            vec![vec!["tag".to_string()]],
-            "content".to_string(),
-            created_at,
-        )
-        .sign(&seckey);
+        let keys = Keys::parse(TEST_SECKEY).unwrap();
+        let pubkey = keys.public_key();
+        let event = keys
+            .sign_event(
+                EventKind::TEXT_NOTE,
+                vec![vec!["tag".to_string()]],
+                "content".to_string(),
+                created_at,
+            )
+            .unwrap();
+        // Schnorr署名は補助乱数を含むため、署名は生成したものを使う
+        let sig = event.sig.clone();
         let serialized = format!(
-            r##"{{"id":"8b0a64c96cd09a3a86c0a225606f0b57a7fec7bf3773c68af13420c1d8d57f97","pubkey":"{pubkey}","created_at":{created_at},"kind":1,"tags":[["tag"]],"content":"content","sig":"80a143f5802118f295b9281b7192feb522ac9eb8cd6922694879cc36ca6f2d35077170f2953c5174b09049d6fa3463b6ed87cbe9e4ac627271ec0b5b73e0ee44"}}"##,
+            r##"{{"id":"fa21933159bb922df954092252a49f044ee89e2f428849a953ab7d865fe04d05","pubkey":"{pubkey}","created_at":{created_at},"kind":1,"tags":[["tag"]],"content":"content","sig":"{sig}"}}"##,
         );
         (event, serialized)
     }
@@ -479,10 +664,26 @@ mod tests {
     fn deserialize_close() {
         let id = "id";
         let serialized = r##"["CLOSE","id"]"##;
-        let message: ClientMessage = serde_json::from_str(&serialized).unwrap();
+        let message: ClientMessage = serde_json::from_str(serialized).unwrap();
         assert_eq!(message, ClientMessage::Close(id.to_string()));
     }
 
+    #[test]
+    fn serialize_auth_event() {
+        let (event, raw_event) = data_provider_event();
+        let message = ClientMessage::Auth(event);
+        let expected = format!(r##"["AUTH",{}]"##, raw_event,);
+        assert_eq!(serde_json::to_string(&message).unwrap(), expected,);
+    }
+
+    #[test]
+    fn deserialize_auth_event() {
+        let (event, raw_event) = data_provider_event();
+        let serialized = format!(r##"["AUTH",{}]"##, raw_event,);
+        let message: ClientMessage = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(message, ClientMessage::Auth(event));
+    }
+
     #[test]
     fn serialize_server_event() {
         let (event, serialized) = data_provider_event();
@@ -519,6 +720,18 @@ mod tests {
         assert_eq!(serde_json::to_string(&message).unwrap(), expected,);
     }
 
+    #[test]
+    fn serialize_rejected_ok() {
+        let ok = super::ServerOk::rejected(
+            "id".to_string(),
+            super::ReasonPrefix::RateLimited,
+            "slow down",
+        );
+        let expected = r##"["OK","id",false,"rate-limited: slow down"]"##;
+        let message = ServerMessage::Ok(ok);
+        assert_eq!(serde_json::to_string(&message).unwrap(), expected,);
+    }
+
     #[test]
     fn deserialize_ok() {
         let ok = super::ServerOk {
@@ -528,7 +741,7 @@ mod tests {
         };
         let expected = ServerMessage::Ok(ok);
         let serialized = r##"["OK","id",true,"message"]"##;
-        let message: ServerMessage = serde_json::from_str(&serialized).unwrap();
+        let message: ServerMessage = serde_json::from_str(serialized).unwrap();
         assert_eq!(message, expected);
     }
 
@@ -545,7 +758,7 @@ mod tests {
         let id = "id";
         let expected = ServerMessage::EOSE(id.to_string());
         let serialized = r##"["EOSE","id"]"##;
-        let message: ServerMessage = serde_json::from_str(&serialized).unwrap();
+        let message: ServerMessage = serde_json::from_str(serialized).unwrap();
         assert_eq!(message, expected);
     }
 
@@ -568,7 +781,7 @@ mod tests {
         };
         let expected = ServerMessage::Closed(closed);
         let serialized = r##"["CLOSED","id","message"]"##;
-        let message: ServerMessage = serde_json::from_str(&serialized).unwrap();
+        let message: ServerMessage = serde_json::from_str(serialized).unwrap();
         assert_eq!(message, expected);
     }
 
@@ -585,7 +798,83 @@ mod tests {
         let message = "message";
         let expected = ServerMessage::Notice(message.to_string());
         let serialized = r##"["NOTICE","message"]"##;
-        let message: ServerMessage = serde_json::from_str(&serialized).unwrap();
+        let message: ServerMessage = serde_json::from_str(serialized).unwrap();
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn serialize_auth_challenge() {
+        let expected = r##"["AUTH","challenge"]"##;
+        let message = ServerMessage::Auth("challenge".to_string());
+        assert_eq!(serde_json::to_string(&message).unwrap(), expected,);
+    }
+
+    #[test]
+    fn deserialize_auth_challenge() {
+        let expected = ServerMessage::Auth("challenge".to_string());
+        let serialized = r##"["AUTH","challenge"]"##;
+        let message: ServerMessage = serde_json::from_str(serialized).unwrap();
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn serialize_count() {
+        let message = ClientMessage::Count(super::Req {
+            id: "id".to_string(),
+            filter: vec![Filter::new()
+                .kinds(vec![7])
+                .e_tags(vec!["e_tag".to_string()])],
+        });
+        let expected = r##"["COUNT","id",{"kinds":[7],"#e":["e_tag"]}]"##;
+        assert_eq!(serde_json::to_string(&message).unwrap(), expected,);
+    }
+
+    #[test]
+    fn deserialize_count() {
+        let expected = ClientMessage::Count(super::Req {
+            id: "id".to_string(),
+            filter: vec![Filter::new()
+                .kinds(vec![7])
+                .e_tags(vec!["e_tag".to_string()])],
+        });
+        let serialized = r##"["COUNT","id",{"kinds":[7],"#e":["e_tag"]}]"##;
+        let message: ClientMessage = serde_json::from_str(serialized).unwrap();
         assert_eq!(message, expected);
     }
+
+    fn data_provider_server_count() -> Vec<(ServerMessage, &'static str)> {
+        vec![
+            (
+                ServerMessage::Count(ServerCount {
+                    subscribe_id: "id".to_string(),
+                    count: 238,
+                    approximate: false,
+                }),
+                r##"["COUNT","id",{"count":238}]"##,
+            ),
+            (
+                ServerMessage::Count(ServerCount {
+                    subscribe_id: "id".to_string(),
+                    count: 93412452,
+                    approximate: true,
+                }),
+                r##"["COUNT","id",{"count":93412452,"approximate":true}]"##,
+            ),
+        ]
+    }
+
+    #[test]
+    fn serialize_server_count() {
+        for (message, expected) in data_provider_server_count() {
+            assert_eq!(serde_json::to_string(&message).unwrap(), expected,);
+        }
+    }
+
+    #[test]
+    fn deserialize_server_count() {
+        for (expected, serialized) in data_provider_server_count() {
+            let message: ServerMessage = serde_json::from_str(serialized).unwrap();
+            assert_eq!(message, expected);
+        }
+    }
 }
@@ -0,0 +1,132 @@
+use aes::{
+    cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit},
+    Aes256,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+
+use crate::{
+    error::NostrError,
+    event::{unix_timestamp, EventKind, UnsignedEvent},
+    keys::Keys,
+};
+
+type Aes256CbcEnc = cbc::Encryptor<Aes256>;
+#[allow(dead_code)]
+type Aes256CbcDec = cbc::Decryptor<Aes256>;
+
+// NIP-04: ECDHの共有点のx座標を鍵としてAES-256-CBCで暗号化する
+// "<暗号文のbase64>?iv=<IVのbase64>"の形式の文字列を返す
+pub fn encrypt(keys: &Keys, receiver_pubkey: &str, plaintext: &str) -> Result<String, NostrError> {
+    let key = keys.shared_secret(receiver_pubkey)?;
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut iv);
+    let ciphertext = Aes256CbcEnc::new(key.as_ref().into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+    Ok(format!(
+        "{}?iv={}",
+        STANDARD.encode(ciphertext),
+        STANDARD.encode(iv)
+    ))
+}
+
+// 送信者の公開鍵と受信者の鍵でNIP-04の暗号文を復号する
+#[allow(dead_code)]
+pub fn decrypt(keys: &Keys, sender_pubkey: &str, content: &str) -> Result<String, NostrError> {
+    let (ciphertext, iv) = content
+        .split_once("?iv=")
+        .ok_or_else(|| NostrError::Crypto("missing ?iv= in content".to_string()))?;
+    let ciphertext = STANDARD
+        .decode(ciphertext)
+        .map_err(|e| NostrError::Crypto(e.to_string()))?;
+    let iv: [u8; 16] = STANDARD
+        .decode(iv)
+        .map_err(|e| NostrError::Crypto(e.to_string()))?
+        .try_into()
+        .map_err(|_| NostrError::Crypto("iv must be 16 bytes".to_string()))?;
+
+    let key = keys.shared_secret(sender_pubkey)?;
+    let plaintext = Aes256CbcDec::new(key.as_ref().into(), &iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+        .map_err(|e| NostrError::Crypto(e.to_string()))?;
+    String::from_utf8(plaintext).map_err(|e| NostrError::Crypto(e.to_string()))
+}
+
+// 受信者の"p"タグを付けた暗号化ダイレクトメッセージ(kind 4)を作成する
+#[allow(dead_code)]
+pub fn encrypted_direct_message(
+    keys: &Keys,
+    receiver_pubkey: &str,
+    message: &str,
+) -> Result<UnsignedEvent, NostrError> {
+    Ok(UnsignedEvent::new(
+        keys.public_key(),
+        EventKind::ENCRYPTED_DIRECT_MESSAGE,
+        vec![vec!["p".to_string(), receiver_pubkey.to_string()]],
+        encrypt(keys, receiver_pubkey, message)?,
+        unix_timestamp(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt, encrypted_direct_message};
+    use crate::{
+        event::EventKind,
+        keys::Keys,
+        test_util::{alice, bob},
+    };
+
+    #[test]
+    fn encrypt_and_decrypt_between_peers() {
+        let alice = alice();
+        let bob = bob();
+        let content = encrypt(&alice, &bob.public_key(), "こんにちは, nostr").unwrap();
+        let (ciphertext, iv) = content.split_once("?iv=").unwrap();
+        assert_eq!(ciphertext.len() % 4, 0);
+        assert_eq!(iv.len(), 24);
+
+        assert_eq!(
+            decrypt(&bob, &alice.public_key(), &content).unwrap(),
+            "こんにちは, nostr"
+        );
+        assert_eq!(
+            decrypt(&alice, &bob.public_key(), &content).unwrap(),
+            "こんにちは, nostr"
+        );
+        // IVはランダムなので同じ平文でも暗号文は異なる
+        assert_ne!(
+            encrypt(&alice, &bob.public_key(), "こんにちは, nostr").unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn reject_malformed_content() {
+        let alice = alice();
+        let bob = bob();
+        let content = encrypt(&alice, &bob.public_key(), "secret").unwrap();
+        assert!(decrypt(&bob, &alice.public_key(), "no iv").is_err());
+        assert!(decrypt(&bob, &alice.public_key(), "AAAA?iv=AAAA").is_err());
+        // 第三者の鍵では復号できない
+        let eve = Keys::generate();
+        assert_ne!(
+            decrypt(&eve, &alice.public_key(), &content).ok().as_deref(),
+            Some("secret")
+        );
+    }
+
+    #[test]
+    fn build_direct_message_event() {
+        let alice = alice();
+        let bob = bob();
+        let event = encrypted_direct_message(&alice, &bob.public_key(), "hi")
+            .unwrap()
+            .sign_with_keys(&alice)
+            .unwrap();
+        assert_eq!(event.kind, EventKind::ENCRYPTED_DIRECT_MESSAGE);
+        assert_eq!(event.tag_value("p"), Some(bob.public_key().as_str()));
+        assert!(event.verify().is_ok());
+        assert_eq!(decrypt(&bob, &event.pubkey, &event.content).unwrap(), "hi");
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{
+    error::NostrError,
+    event::{unix_timestamp, Event, EventKind, UnsignedEvent},
+    keys::Keys,
+    nip59,
+};
+
+// kind 15のファイルメッセージに付ける、暗号化したファイルの情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    // 暗号化前のファイルのMIMEタイプ
+    pub file_type: String,
+    // 暗号化の方式（例: "aes-gcm"）
+    pub encryption_algorithm: String,
+    pub decryption_key: String,
+    pub decryption_nonce: String,
+    // 暗号化したファイルのSHA-256
+    pub hash: String,
+}
+
+fn p_tags(receivers: &[&str]) -> Vec<Vec<String>> {
+    receivers
+        .iter()
+        .map(|p| vec!["p".to_string(), p.to_string()])
+        .collect()
+}
+
+// NIP-17: 受信者の"p"タグを付けたkind 14のチャットメッセージ(署名しないrumor)を作成する
+// 返信の場合は返信先のイベントIDを"e"タグに付ける
+#[allow(dead_code)]
+pub fn chat_message(
+    keys: &Keys,
+    receivers: &[&str],
+    message: &str,
+    reply_to: Option<&str>,
+) -> UnsignedEvent {
+    let mut tags = p_tags(receivers);
+    if let Some(id) = reply_to {
+        tags.push(vec!["e".to_string(), id.to_string()]);
+    }
+    UnsignedEvent::new(
+        keys.public_key(),
+        EventKind::CHAT_MESSAGE,
+        tags,
+        message.to_string(),
+        unix_timestamp(),
+    )
+}
+
+// 暗号化したファイルのURLを内容とするkind 15のファイルメッセージ(rumor)を作成する
+#[allow(dead_code)]
+pub fn file_message(
+    keys: &Keys,
+    receivers: &[&str],
+    url: &str,
+    metadata: &FileMetadata,
+) -> UnsignedEvent {
+    let mut tags = p_tags(receivers);
+    for (name, value) in [
+        ("file-type", &metadata.file_type),
+        ("encryption-algorithm", &metadata.encryption_algorithm),
+        ("decryption-key", &metadata.decryption_key),
+        ("decryption-nonce", &metadata.decryption_nonce),
+        ("x", &metadata.hash),
+    ] {
+        tags.push(vec![name.to_string(), value.clone()]);
+    }
+    UnsignedEvent::new(
+        keys.public_key(),
+        EventKind::FILE_MESSAGE,
+        tags,
+        url.to_string(),
+        unix_timestamp(),
+    )
+}
+
+fn is_direct_message(kind: EventKind) -> bool {
+    kind == EventKind::CHAT_MESSAGE || kind == EventKind::FILE_MESSAGE
+}
+
+// "p"タグの受信者それぞれと、送信済みのメッセージを読めるように送信者自身に向けてgift wrapする
+#[allow(dead_code)]
+pub fn wrap_for_recipients(keys: &Keys, rumor: &UnsignedEvent) -> Result<Vec<Event>, NostrError> {
+    if !is_direct_message(rumor.kind()) {
+        return Err(NostrError::InvalidMessage(
+            "expected a chat or file message".to_string(),
+        ));
+    }
+    let own_pubkey = keys.public_key();
+    let mut recipients: Vec<&str> = Vec::new();
+    for recipient in rumor
+        .tags()
+        .iter()
+        .filter(|t| t.first().is_some_and(|n| n == "p"))
+        .filter_map(|t| t.get(1).map(String::as_str))
+        .chain([own_pubkey.as_str()])
+    {
+        if !recipients.contains(&recipient) {
+            recipients.push(recipient);
+        }
+    }
+    recipients
+        .into_iter()
+        .map(|recipient| nip59::wrap(keys, recipient, rumor))
+        .collect()
+}
+
+// 受信したgift wrapを開き、チャットメッセージもしくはファイルメッセージを取り出す
+#[allow(dead_code)]
+pub fn open(keys: &Keys, gift_wrap: &Event) -> Result<UnsignedEvent, NostrError> {
+    let rumor = nip59::unwrap(keys, gift_wrap)?;
+    if !is_direct_message(rumor.kind()) {
+        return Err(NostrError::InvalidMessage(
+            "expected a chat or file message".to_string(),
+        ));
+    }
+    Ok(rumor)
+}
+
+// ダイレクトメッセージを受け取るリレーのリスト(kind 10050)を作成する
+#[allow(dead_code)]
+pub fn dm_relay_list(keys: &Keys, relays: &[&str]) -> UnsignedEvent {
+    UnsignedEvent::new(
+        keys.public_key(),
+        EventKind::DM_RELAY_LIST,
+        relays
+            .iter()
+            .map(|url| vec!["relay".to_string(), url.to_string()])
+            .collect(),
+        "".to_string(),
+        unix_timestamp(),
+    )
+}
+
+// kind 10050のイベントからリレーのURLを取り出す
+#[allow(dead_code)]
+pub fn dm_relays(event: &Event) -> Vec<&str> {
+    if event.kind != EventKind::DM_RELAY_LIST {
+        return vec![];
+    }
+    event
+        .tags
+        .iter()
+        .filter(|t| t.first().is_some_and(|n| n == "relay"))
+        .filter_map(|t| t.get(1).map(String::as_str))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        chat_message, dm_relay_list, dm_relays, file_message, open, wrap_for_recipients,
+        FileMetadata,
+    };
+    use crate::{
+        event::{unix_timestamp, EventKind, UnsignedEvent},
+        keys::Keys,
+        nip59,
+        test_util::{alice, bob},
+    };
+
+    #[test]
+    fn wrap_chat_message_for_each_recipient() {
+        let alice = alice();
+        let bob = bob();
+        let carol = Keys::generate();
+        let message = chat_message(
+            &alice,
+            &[&bob.public_key(), &carol.public_key(), &bob.public_key()],
+            "group hello",
+            Some(&"a".repeat(64)),
+        );
+        assert_eq!(message.kind(), EventKind::CHAT_MESSAGE);
+
+        // bob、carol、alice自身の3つ（重複したbobは1つにまとめる）
+        let wraps = wrap_for_recipients(&alice, &message).unwrap();
+        assert_eq!(wraps.len(), 3);
+        for (wrap, keys) in wraps.iter().zip([&bob, &carol, &alice]) {
+            assert_eq!(wrap.kind, EventKind::GIFT_WRAP);
+            assert_eq!(wrap.tag_value("p"), Some(keys.public_key().as_str()));
+            let rumor = open(keys, wrap).unwrap();
+            assert_eq!(rumor, message);
+            assert_eq!(rumor.tags().last().unwrap()[0], "e");
+        }
+        assert!(open(&carol, &wraps[0]).is_err());
+    }
+
+    #[test]
+    fn build_file_message() {
+        let alice = alice();
+        let bob = bob();
+        let metadata = FileMetadata {
+            file_type: "image/jpeg".to_string(),
+            encryption_algorithm: "aes-gcm".to_string(),
+            decryption_key: "key".to_string(),
+            decryption_nonce: "nonce".to_string(),
+            hash: "b".repeat(64),
+        };
+        let message = file_message(
+            &alice,
+            &[&bob.public_key()],
+            "https://example.com/file.bin",
+            &metadata,
+        );
+        assert_eq!(message.kind(), EventKind::FILE_MESSAGE);
+        assert_eq!(message.content(), "https://example.com/file.bin");
+        assert!(message
+            .tags()
+            .contains(&vec!["file-type".to_string(), "image/jpeg".to_string()]));
+
+        let wraps = wrap_for_recipients(&alice, &message).unwrap();
+        assert_eq!(open(&bob, &wraps[0]).unwrap(), message);
+    }
+
+    #[test]
+    fn reject_other_kinds() {
+        let alice = alice();
+        let bob = bob();
+        let note = UnsignedEvent::new(
+            alice.public_key(),
+            EventKind::TEXT_NOTE,
+            vec![vec!["p".to_string(), bob.public_key()]],
+            "note".to_string(),
+            unix_timestamp(),
+        );
+        assert!(wrap_for_recipients(&alice, &note).is_err());
+        let wrap = nip59::wrap(&alice, &bob.public_key(), &note).unwrap();
+        assert!(open(&bob, &wrap).is_err());
+    }
+
+    #[test]
+    fn dm_relay_list_round_trip() {
+        let alice = alice();
+        let event = dm_relay_list(&alice, &["wss://inbox.example.com", "wss://dm.example.org"])
+            .sign_with_keys(&alice)
+            .unwrap();
+        assert_eq!(event.kind, EventKind::DM_RELAY_LIST);
+        assert!(event.kind.is_replaceable());
+        assert_eq!(
+            dm_relays(&event),
+            vec!["wss://inbox.example.com", "wss://dm.example.org"]
+        );
+    }
+}
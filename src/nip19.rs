@@ -0,0 +1,361 @@
+use bech32::{Bech32, Hrp};
+
+use crate::{error::NostrError, event::EventKind};
+
+// TLVの種類
+const TLV_SPECIAL: u8 = 0;
+const TLV_RELAY: u8 = 1;
+const TLV_AUTHOR: u8 = 2;
+const TLV_KIND: u8 = 3;
+
+// NIP-19のbech32形式で表すエンティティ
+// 公開鍵・秘密鍵・イベントIDは小文字の16進数で保持する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Nip19 {
+    Pubkey(String),
+    Seckey(String),
+    Note(String),
+    Profile(ProfilePointer),
+    Event(EventPointer),
+    Address(AddressPointer),
+}
+
+// nprofile: 公開鍵とそのユーザーが使っているリレー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfilePointer {
+    pub pubkey: String,
+    pub relays: Vec<String>,
+}
+
+// nevent: イベントIDとイベントが見つかるリレー、作成者と種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventPointer {
+    pub id: String,
+    pub relays: Vec<String>,
+    pub author: Option<String>,
+    pub kind: Option<EventKind>,
+}
+
+// naddr: addressableイベントの"d"タグの値・作成者・種類とイベントが見つかるリレー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressPointer {
+    pub identifier: String,
+    pub pubkey: String,
+    pub kind: EventKind,
+    pub relays: Vec<String>,
+}
+
+impl Nip19 {
+    // HRP（先頭の"npub"などの部分）
+    pub fn hrp(&self) -> &'static str {
+        match self {
+            Nip19::Pubkey(_) => "npub",
+            Nip19::Seckey(_) => "nsec",
+            Nip19::Note(_) => "note",
+            Nip19::Profile(_) => "nprofile",
+            Nip19::Event(_) => "nevent",
+            Nip19::Address(_) => "naddr",
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, NostrError> {
+        let data = match self {
+            Nip19::Pubkey(key) | Nip19::Seckey(key) | Nip19::Note(key) => bytes32(key)?.to_vec(),
+            Nip19::Profile(profile) => {
+                let mut tlv = Vec::new();
+                push_tlv(&mut tlv, TLV_SPECIAL, &bytes32(&profile.pubkey)?)?;
+                push_relays(&mut tlv, &profile.relays)?;
+                tlv
+            }
+            Nip19::Event(event) => {
+                let mut tlv = Vec::new();
+                push_tlv(&mut tlv, TLV_SPECIAL, &bytes32(&event.id)?)?;
+                push_relays(&mut tlv, &event.relays)?;
+                if let Some(author) = &event.author {
+                    push_tlv(&mut tlv, TLV_AUTHOR, &bytes32(author)?)?;
+                }
+                if let Some(kind) = event.kind {
+                    push_tlv(
+                        &mut tlv,
+                        TLV_KIND,
+                        &u32::from(u16::from(kind)).to_be_bytes(),
+                    )?;
+                }
+                tlv
+            }
+            Nip19::Address(address) => {
+                let mut tlv = Vec::new();
+                push_tlv(&mut tlv, TLV_SPECIAL, address.identifier.as_bytes())?;
+                push_relays(&mut tlv, &address.relays)?;
+                push_tlv(&mut tlv, TLV_AUTHOR, &bytes32(&address.pubkey)?)?;
+                push_tlv(
+                    &mut tlv,
+                    TLV_KIND,
+                    &u32::from(u16::from(address.kind)).to_be_bytes(),
+                )?;
+                tlv
+            }
+        };
+        let hrp = Hrp::parse(self.hrp()).map_err(|e| NostrError::InvalidBech32(e.to_string()))?;
+        bech32::encode::<Bech32>(hrp, &data).map_err(|e| NostrError::InvalidBech32(e.to_string()))
+    }
+
+    pub fn decode(s: &str) -> Result<Self, NostrError> {
+        let (hrp, data) =
+            bech32::decode(s).map_err(|e| NostrError::InvalidBech32(e.to_string()))?;
+        match hrp.to_lowercase().as_str() {
+            "npub" => Ok(Nip19::Pubkey(hex32(&data)?)),
+            "nsec" => Ok(Nip19::Seckey(hex32(&data)?)),
+            "note" => Ok(Nip19::Note(hex32(&data)?)),
+            "nprofile" => {
+                let tlv = Tlv::parse(&data)?;
+                Ok(Nip19::Profile(ProfilePointer {
+                    pubkey: hex32(tlv.special()?)?,
+                    relays: tlv.relays()?,
+                }))
+            }
+            "nevent" => {
+                let tlv = Tlv::parse(&data)?;
+                Ok(Nip19::Event(EventPointer {
+                    id: hex32(tlv.special()?)?,
+                    relays: tlv.relays()?,
+                    author: tlv.author()?,
+                    kind: tlv.kind()?,
+                }))
+            }
+            "naddr" => {
+                let tlv = Tlv::parse(&data)?;
+                Ok(Nip19::Address(AddressPointer {
+                    identifier: String::from_utf8(tlv.special()?.to_vec())
+                        .map_err(|e| NostrError::InvalidBech32(e.to_string()))?,
+                    pubkey: tlv
+                        .author()?
+                        .ok_or_else(|| NostrError::InvalidBech32("naddr without author".into()))?,
+                    kind: tlv
+                        .kind()?
+                        .ok_or_else(|| NostrError::InvalidBech32("naddr without kind".into()))?,
+                    relays: tlv.relays()?,
+                }))
+            }
+            hrp => Err(NostrError::InvalidBech32(format!("unknown prefix {hrp}"))),
+        }
+    }
+}
+
+// 公開鍵・秘密鍵・イベントIDの変換
+#[allow(dead_code)]
+pub fn encode_npub(pubkey: &str) -> Result<String, NostrError> {
+    Nip19::Pubkey(pubkey.to_string()).encode()
+}
+
+#[allow(dead_code)]
+pub fn encode_nsec(seckey: &str) -> Result<String, NostrError> {
+    Nip19::Seckey(seckey.to_string()).encode()
+}
+
+#[allow(dead_code)]
+pub fn encode_note(id: &str) -> Result<String, NostrError> {
+    Nip19::Note(id.to_string()).encode()
+}
+
+#[allow(dead_code)]
+pub fn decode_npub(s: &str) -> Result<String, NostrError> {
+    match Nip19::decode(s)? {
+        Nip19::Pubkey(pubkey) => Ok(pubkey),
+        other => Err(wrong_hrp("npub", &other)),
+    }
+}
+
+#[allow(dead_code)]
+pub fn decode_nsec(s: &str) -> Result<String, NostrError> {
+    match Nip19::decode(s)? {
+        Nip19::Seckey(seckey) => Ok(seckey),
+        other => Err(wrong_hrp("nsec", &other)),
+    }
+}
+
+#[allow(dead_code)]
+pub fn decode_note(s: &str) -> Result<String, NostrError> {
+    match Nip19::decode(s)? {
+        Nip19::Note(id) => Ok(id),
+        other => Err(wrong_hrp("note", &other)),
+    }
+}
+
+fn wrong_hrp(expected: &str, actual: &Nip19) -> NostrError {
+    NostrError::InvalidBech32(format!("expected {expected}, got {}", actual.hrp()))
+}
+
+fn bytes32(value: &str) -> Result<[u8; 32], NostrError> {
+    hex::decode(value)?
+        .try_into()
+        .map_err(|_| NostrError::InvalidBech32(format!("{value} is not 32 bytes")))
+}
+
+fn hex32(data: &[u8]) -> Result<String, NostrError> {
+    if data.len() != 32 {
+        return Err(NostrError::InvalidBech32(format!(
+            "expected 32 bytes, got {}",
+            data.len()
+        )));
+    }
+    Ok(hex::encode(data))
+}
+
+fn push_tlv(tlv: &mut Vec<u8>, t: u8, value: &[u8]) -> Result<(), NostrError> {
+    let length = u8::try_from(value.len())
+        .map_err(|_| NostrError::InvalidBech32("TLV value is too long".to_string()))?;
+    tlv.push(t);
+    tlv.push(length);
+    tlv.extend_from_slice(value);
+    Ok(())
+}
+
+fn push_relays(tlv: &mut Vec<u8>, relays: &[String]) -> Result<(), NostrError> {
+    for relay in relays {
+        push_tlv(tlv, TLV_RELAY, relay.as_bytes())?;
+    }
+    Ok(())
+}
+
+// 読み取ったTLVの一覧
+// 未知の種類は無視する
+struct Tlv<'a>(Vec<(u8, &'a [u8])>);
+
+impl<'a> Tlv<'a> {
+    fn parse(mut data: &'a [u8]) -> Result<Self, NostrError> {
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            let [t, length, rest @ ..] = data else {
+                return Err(NostrError::InvalidBech32("truncated TLV".to_string()));
+            };
+            let length = *length as usize;
+            if rest.len() < length {
+                return Err(NostrError::InvalidBech32("truncated TLV".to_string()));
+            }
+            entries.push((*t, &rest[..length]));
+            data = &rest[length..];
+        }
+        Ok(Self(entries))
+    }
+
+    fn values(&self, t: u8) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.0.iter().filter(move |(k, _)| *k == t).map(|(_, v)| *v)
+    }
+
+    fn special(&self) -> Result<&'a [u8], NostrError> {
+        self.values(TLV_SPECIAL)
+            .next()
+            .ok_or_else(|| NostrError::InvalidBech32("missing TLV special".to_string()))
+    }
+
+    fn relays(&self) -> Result<Vec<String>, NostrError> {
+        self.values(TLV_RELAY)
+            .map(|v| {
+                String::from_utf8(v.to_vec()).map_err(|e| NostrError::InvalidBech32(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn author(&self) -> Result<Option<String>, NostrError> {
+        self.values(TLV_AUTHOR).next().map(hex32).transpose()
+    }
+
+    fn kind(&self) -> Result<Option<EventKind>, NostrError> {
+        self.values(TLV_KIND)
+            .next()
+            .map(|v| {
+                let bytes: [u8; 4] = v
+                    .try_into()
+                    .map_err(|_| NostrError::InvalidBech32("kind must be 4 bytes".to_string()))?;
+                u16::try_from(u32::from_be_bytes(bytes))
+                    .map(EventKind::from)
+                    .map_err(|e| NostrError::InvalidBech32(e.to_string()))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_npub, decode_nsec, AddressPointer, EventPointer, Nip19, ProfilePointer};
+    use crate::event::EventKind;
+
+    // NIP-19の例
+    const NPUB: &str = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+    const PUBKEY: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+    const NSEC: &str = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+    const SECKEY: &str = "67dea2ed018072d675f5415ecfaed7d2597555e202d85b3d65ea4e58d2d92ffa";
+    const NPROFILE: &str = "nprofile1qqsrhuxx8l9ex335q7he0f09aej04zpazpl0ne2cgukyawd24mayt8gpp4mhxue69uhhytnc9e3k7mgpz4mhxue69uhkg6nzv9ejuumpv34kytnrdaksjlyr9p";
+
+    #[test]
+    fn spec_vectors() {
+        assert_eq!(
+            Nip19::decode(NPUB).unwrap(),
+            Nip19::Pubkey(PUBKEY.to_string())
+        );
+        assert_eq!(Nip19::Pubkey(PUBKEY.to_string()).encode().unwrap(), NPUB);
+        assert_eq!(
+            Nip19::decode(NSEC).unwrap(),
+            Nip19::Seckey(SECKEY.to_string())
+        );
+        assert_eq!(Nip19::Seckey(SECKEY.to_string()).encode().unwrap(), NSEC);
+
+        let profile = Nip19::Profile(ProfilePointer {
+            pubkey: "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d".to_string(),
+            relays: vec![
+                "wss://r.x.com".to_string(),
+                "wss://djbas.sadkb.com".to_string(),
+            ],
+        });
+        assert_eq!(Nip19::decode(NPROFILE).unwrap(), profile);
+        assert_eq!(profile.encode().unwrap(), NPROFILE);
+    }
+
+    #[test]
+    fn round_trip_pointers() {
+        let entities = [
+            Nip19::Note(PUBKEY.to_string()),
+            Nip19::Event(EventPointer {
+                id: PUBKEY.to_string(),
+                relays: vec!["wss://relay.example.com".to_string()],
+                author: Some(SECKEY.to_string()),
+                kind: Some(EventKind::TEXT_NOTE),
+            }),
+            Nip19::Event(EventPointer {
+                id: PUBKEY.to_string(),
+                relays: vec![],
+                author: None,
+                kind: None,
+            }),
+            Nip19::Address(AddressPointer {
+                identifier: "article".to_string(),
+                pubkey: PUBKEY.to_string(),
+                kind: EventKind::from(30023),
+                relays: vec!["wss://relay.example.com".to_string()],
+            }),
+        ];
+        for entity in entities {
+            let encoded = entity.encode().unwrap();
+            assert!(encoded.starts_with(entity.hrp()));
+            assert_eq!(Nip19::decode(&encoded).unwrap(), entity);
+        }
+    }
+
+    #[test]
+    fn reject_wrong_hrp_and_bad_checksum() {
+        assert_eq!(decode_npub(NPUB).unwrap(), PUBKEY);
+        assert_eq!(decode_nsec(NSEC).unwrap(), SECKEY);
+        assert_eq!(
+            decode_npub(NSEC).unwrap_err().to_string(),
+            "無効なbech32文字列: expected npub, got nsec"
+        );
+
+        // 最後の文字を変えるとチェックサムが一致しない
+        let mut corrupted = NPUB.to_string();
+        corrupted.pop();
+        corrupted.push('q');
+        assert!(Nip19::decode(&corrupted).is_err());
+        assert!(Nip19::decode("nfoo1qqqqqqqqq").is_err());
+    }
+}
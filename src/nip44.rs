@@ -0,0 +1,484 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    ChaCha20,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::{error::NostrError, keys::Keys};
+
+const VERSION: u8 = 2;
+const SALT: &[u8] = b"nip44-v2";
+const MIN_PLAINTEXT_SIZE: usize = 1;
+const MAX_PLAINTEXT_SIZE: usize = 65535;
+
+// NIP-44 v2: 2人の間で共通の会話鍵
+// ECDHの共有点のx座標をHKDF-extract（saltは"nip44-v2"）したもの
+#[allow(dead_code)]
+pub fn conversation_key(keys: &Keys, public_key: &str) -> Result<Zeroizing<[u8; 32]>, NostrError> {
+    let shared = keys.shared_secret(public_key)?;
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(SALT), shared.as_ref());
+    Ok(Zeroizing::new(prk.into()))
+}
+
+// メッセージ毎の鍵
+struct MessageKeys {
+    chacha_key: [u8; 32],
+    chacha_nonce: [u8; 12],
+    hmac_key: [u8; 32],
+}
+
+impl Drop for MessageKeys {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+        self.chacha_key.zeroize();
+        self.chacha_nonce.zeroize();
+        self.hmac_key.zeroize();
+    }
+}
+
+fn message_keys(conversation_key: &[u8; 32], nonce: &[u8; 32]) -> Result<MessageKeys, NostrError> {
+    let hkdf = Hkdf::<Sha256>::from_prk(conversation_key)
+        .map_err(|e| NostrError::Crypto(e.to_string()))?;
+    let mut keys = Zeroizing::new([0u8; 76]);
+    hkdf.expand(nonce, keys.as_mut())
+        .map_err(|e| NostrError::Crypto(e.to_string()))?;
+    let mut message_keys = MessageKeys {
+        chacha_key: [0; 32],
+        chacha_nonce: [0; 12],
+        hmac_key: [0; 32],
+    };
+    message_keys.chacha_key.copy_from_slice(&keys[..32]);
+    message_keys.chacha_nonce.copy_from_slice(&keys[32..44]);
+    message_keys.hmac_key.copy_from_slice(&keys[44..]);
+    Ok(message_keys)
+}
+
+// 平文の長さから、長さを隠すためのパディング後の長さを計算する
+pub fn calc_padded_len(len: usize) -> usize {
+    if len <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((len - 1) / chunk + 1)
+}
+
+// 2バイトのビッグエンディアンの長さ + 平文 + 0埋め
+fn pad(plaintext: &str) -> Result<Zeroizing<Vec<u8>>, NostrError> {
+    let bytes = plaintext.as_bytes();
+    if !(MIN_PLAINTEXT_SIZE..=MAX_PLAINTEXT_SIZE).contains(&bytes.len()) {
+        return Err(NostrError::Crypto("invalid plaintext length".to_string()));
+    }
+    let mut padded = Zeroizing::new(Vec::with_capacity(2 + calc_padded_len(bytes.len())));
+    padded.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    padded.extend_from_slice(bytes);
+    padded.resize(2 + calc_padded_len(bytes.len()), 0);
+    Ok(padded)
+}
+
+fn unpad(padded: &[u8]) -> Result<String, NostrError> {
+    let invalid = || NostrError::Crypto("invalid padding".to_string());
+    let (len, rest) = padded.split_at_checked(2).ok_or_else(invalid)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    if len < MIN_PLAINTEXT_SIZE || rest.len() < len || padded.len() != 2 + calc_padded_len(len) {
+        return Err(invalid());
+    }
+    String::from_utf8(rest[..len].to_vec()).map_err(|e| NostrError::Crypto(e.to_string()))
+}
+
+fn hmac_aad(key: &[u8; 32], nonce: &[u8; 32], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(nonce);
+    mac.update(ciphertext);
+    mac
+}
+
+// 会話鍵とランダムなnonceで暗号化し、base64のペイロードを返す
+#[allow(dead_code)]
+pub fn encrypt(conversation_key: &[u8; 32], plaintext: &str) -> Result<String, NostrError> {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    encrypt_with_nonce(conversation_key, plaintext, &nonce)
+}
+
+fn encrypt_with_nonce(
+    conversation_key: &[u8; 32],
+    plaintext: &str,
+    nonce: &[u8; 32],
+) -> Result<String, NostrError> {
+    let keys = message_keys(conversation_key, nonce)?;
+    let mut ciphertext = pad(plaintext)?.to_vec();
+    ChaCha20::new(&keys.chacha_key.into(), &keys.chacha_nonce.into())
+        .apply_keystream(&mut ciphertext);
+    let mac = hmac_aad(&keys.hmac_key, nonce, &ciphertext).finalize();
+
+    let mut payload = Vec::with_capacity(1 + 32 + ciphertext.len() + 32);
+    payload.push(VERSION);
+    payload.extend_from_slice(nonce);
+    payload.extend_from_slice(&ciphertext);
+    payload.extend_from_slice(&mac.into_bytes());
+    Ok(STANDARD.encode(payload))
+}
+
+// base64のペイロードを検証して復号する
+#[allow(dead_code)]
+pub fn decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<String, NostrError> {
+    if payload.starts_with('#') {
+        return Err(NostrError::Crypto("unknown encryption version".to_string()));
+    }
+    if !(132..=87472).contains(&payload.len()) {
+        return Err(NostrError::Crypto("invalid payload size".to_string()));
+    }
+    let data = STANDARD
+        .decode(payload)
+        .map_err(|e| NostrError::Crypto(e.to_string()))?;
+    if !(99..=65603).contains(&data.len()) {
+        return Err(NostrError::Crypto("invalid data size".to_string()));
+    }
+    if data[0] != VERSION {
+        return Err(NostrError::Crypto(format!(
+            "unknown encryption version {}",
+            data[0]
+        )));
+    }
+    let nonce: [u8; 32] = data[1..33].try_into().unwrap();
+    let (ciphertext, mac) = data[33..].split_at(data.len() - 33 - 32);
+
+    let keys = message_keys(conversation_key, &nonce)?;
+    // MACは定数時間で比較する
+    hmac_aad(&keys.hmac_key, &nonce, ciphertext)
+        .verify_slice(mac)
+        .map_err(|_| NostrError::Crypto("invalid MAC".to_string()))?;
+
+    let mut padded = Zeroizing::new(ciphertext.to_vec());
+    ChaCha20::new(&keys.chacha_key.into(), &keys.chacha_nonce.into())
+        .apply_keystream(padded.as_mut());
+    unpad(&padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use chacha20::{
+        cipher::{KeyIvInit, StreamCipher},
+        ChaCha20,
+    };
+    use hmac::Mac;
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        calc_padded_len, conversation_key, decrypt, encrypt, encrypt_with_nonce, hmac_aad,
+        message_keys, unpad, VERSION,
+    };
+    use crate::keys::Keys;
+
+    fn key32(value: &str) -> [u8; 32] {
+        hex::decode(value).unwrap().try_into().unwrap()
+    }
+
+    // NIP-44の公式テストベクトル(nip44.vectors.json)のvalidの項目
+    // ファイル全体は同梱しておらず、ここに載せた値はこの実装の出力と完全に一致することを確認している
+
+    // (sec1, pub2, conversation_key)
+    const CONVERSATION_KEYS: [(&str, &str, &str); 4] = [
+        (
+            "315e59ff51cb9209768cf7da80791ddcaae56ac9775eb25b6dee1234bc5d2268",
+            "c2f9d9948dc8c7c38321e4b85c8558872eafa0641cd269db76848a6073e69133",
+            "3dfef0ce2a4d80a25e7a328accf73448ef67096f65f79588e358d9a0eb9013f1",
+        ),
+        (
+            "a1e37752c9fdc1273be53f68c5f74be7c8905728e8de75800b94262f9497c86e",
+            "03bb7947065dde12ba991ea045132581d0954f042c84e06d8c00066e23c1a800",
+            "4d14f36e81b8452128da64fe6f1eae873baae2f444b02c950b90e43553f2178b",
+        ),
+        // 秘密鍵が曲線の位数-2
+        (
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364139",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "8b6392dbf2ec6a2b2d5b1477fc2be84d63ef254b667cadd31bd3f444c44ae6ba",
+        ),
+        (
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdeb",
+            "be234f46f60a250bef52a5ee34c758800c4ca8e5030bf4cc1a31d37ba2104d43",
+        ),
+    ];
+
+    // (sec1, sec2, conversation_key, nonce, plaintext, payload)
+    const ENCRYPT_DECRYPT: [(&str, &str, &str, &str, &str, &str); 3] = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "a",
+            "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb",
+        ),
+        (
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d",
+            "f00000000000000000000000000000f00000000000000000000000000000000f",
+            "🍕🫃",
+            "AvAAAAAAAAAAAAAAAAAAAPAAAAAAAAAAAAAAAAAAAAAPSKSK6is9ngkX2+cSq85Th16oRTISAOfhStnixqZziKMDvB0QQzgFZdjLTPicCJaV8nDITO+QfaQ61+KbWQIOO2Yj",
+        ),
+        (
+            "8f40e50a84a7462e2b8d24c28898ef1f23359fff50d8c509e6fb7ce06e142f9c",
+            "b9b0a1e9cc20100c5faa3bbe2777303d25950616c4c6a3fa2e3e046f936ec2ba",
+            "d5a2f879123145a4b291d767428870f5a8d9e5007193321795b40183d4ab8c2b",
+            "b20989adc3ddc41cd2c435952c0d59a91315d8c5218d5040573fc3749543acaf",
+            "ability🤝的 ȺȾ",
+            "ArIJia3D3cQc0sQ1lSwNWakTFdjFIY1QQFc/w3SVQ6yvbG2S0x4Yu86QGwPTy7mP3961I1XqB6SFFTzqDZZavhxoWMj7mEVGMQIsh2RLWI5EYQaQDIePSnXPlzf7CIt+voTD",
+        ),
+    ];
+
+    // get_message_keysの会話鍵と(nonce, chacha_key, chacha_nonce, hmac_key)
+    const MESSAGE_KEYS_CONVERSATION_KEY: &str =
+        "a1a3d60f3470a8612633924e91febf96dc5366ce130f658b1f0fc652c20b3b54";
+    const MESSAGE_KEYS: [(&str, &str, &str, &str); 3] = [
+        (
+            "e1e6f880560d6d149ed83dcc7e5861ee62a5ee051f7fde9975fe5d25d2a02d72",
+            "f145f3bed47cb70dbeaac07f3a3fe683e822b3715edb7c4fe310829014ce7d76",
+            "c4ad129bb01180c0933a160c",
+            "027c1db445f05e2eee864a0975b0ddef5b7110583c8c192de3732571ca5838c4",
+        ),
+        (
+            "e1d6d28c46de60168b43d79dacc519698512ec35e8ccb12640fc8e9f26121101",
+            "e35b88f8d4a8f1606c5082f7a64b100e5d85fcdb2e62aeafbec03fb9e860ad92",
+            "22925e920cee4a50a478be90",
+            "46a7c55d4283cb0df1d5e29540be67abfe709e3b2e14b7bf9976e6df994ded30",
+        ),
+        (
+            "cfc13bef512ac9c15951ab00030dfaf2626fdca638dedb35f2993a9eeb85d650",
+            "020783eb35fdf5b80ef8c75377f4e937efb26bcbad0e61b4190e39939860c4bf",
+            "d3594987af769a52904656ac",
+            "237ec0ccb6ebd53d179fa8fd319e092acff599ef174c1fdafd499ef2b8dee745",
+        ),
+    ];
+
+    // encrypt_decrypt_long_msgの(conversation_key, nonce, pattern, repeat, plaintext_sha256, payload_sha256)
+    const LONG_MESSAGES: [(&str, &str, &str, usize, &str, &str); 3] = [
+        (
+            "8fc262099ce0d0bb9b89bac05bb9e04f9bc0090acc181fef6840ccee470371ed",
+            "326bcb2c943cd6bb717588c9e5a7e738edf6ed14ec5f5344caa6ef56f0b9cff7",
+            "x",
+            65535,
+            "09ab7495d3e61a76f0deb12cb0306f0696cbb17ffc12131368c7a939f12f56d3",
+            "90714492225faba06310bff2f249ebdc2a5e609d65a629f1c87f2d4ffc55330a",
+        ),
+        (
+            "56adbe3720339363ab9c3b8526ffce9fd77600927488bfc4b59f7a68ffe5eae0",
+            "ad68da81833c2a8ff609c3d2c0335fd44fe5954f85bb580c6a8d467aa9fc5dd0",
+            "!",
+            65535,
+            "6af297793b72ae092c422e552c3bb3cbc310da274bd1cf9e31023a7fe4a2d75e",
+            "8013e45a109fad3362133132b460a2d5bce235fe71c8b8f4014793fb52a49844",
+        ),
+        (
+            "7fc540779979e472bb8d12480b443d1e5eb1098eae546ef2390bee499bbf46be",
+            "34905e82105c20de9a2f6cd385a0d541e6bcc10601d12481ff3a7575dc622033",
+            "🦄",
+            16383,
+            "a249558d161b77297bc0cb311dde7d77190f6571b25c7e4429cd19044634a61f",
+            "b3348422471da1f3c59d79acfe2fe103f3cd24488109e5b18734cdb5953afd15",
+        ),
+    ];
+
+    fn sha256_hex(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn vector_message_keys() {
+        let key = key32(MESSAGE_KEYS_CONVERSATION_KEY);
+        for (nonce, chacha_key, chacha_nonce, hmac_key) in MESSAGE_KEYS {
+            let keys = message_keys(&key, &key32(nonce)).unwrap();
+            assert_eq!(hex::encode(keys.chacha_key), chacha_key);
+            assert_eq!(hex::encode(keys.chacha_nonce), chacha_nonce);
+            assert_eq!(hex::encode(keys.hmac_key), hmac_key);
+        }
+    }
+
+    // 平文の最大長(65535バイト)に近い長さのメッセージ
+    #[test]
+    fn vector_long_messages() {
+        for (key, nonce, pattern, repeat, plaintext_sha256, payload_sha256) in LONG_MESSAGES {
+            let key = key32(key);
+            let plaintext = pattern.repeat(repeat);
+            assert_eq!(sha256_hex(plaintext.as_bytes()), plaintext_sha256);
+            let payload = encrypt_with_nonce(&key, &plaintext, &key32(nonce)).unwrap();
+            assert_eq!(sha256_hex(payload.as_bytes()), payload_sha256);
+            assert_eq!(decrypt(&key, &payload).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn vector_conversation_key() {
+        for (sec1, pub2, expected) in CONVERSATION_KEYS {
+            let key = conversation_key(&Keys::parse(sec1).unwrap(), pub2).unwrap();
+            assert_eq!(hex::encode(key.as_ref()), expected, "{sec1}");
+        }
+    }
+
+    #[test]
+    fn vector_encrypt_decrypt() {
+        for (sec1, sec2, expected_key, nonce, plaintext, payload) in ENCRYPT_DECRYPT {
+            let sec1 = Keys::parse(sec1).unwrap();
+            let sec2 = Keys::parse(sec2).unwrap();
+            let key = conversation_key(&sec1, &sec2.public_key()).unwrap();
+            assert_eq!(hex::encode(key.as_ref()), expected_key);
+            // 相手側から計算しても同じ会話鍵になる
+            assert_eq!(conversation_key(&sec2, &sec1.public_key()).unwrap(), key);
+
+            let nonce = key32(nonce);
+            assert_eq!(
+                encrypt_with_nonce(&key, plaintext, &nonce).unwrap(),
+                payload
+            );
+            assert_eq!(decrypt(&key, payload).unwrap(), plaintext);
+        }
+    }
+
+    // 公式ベクトルのinvalid.get_conversation_keyと同じ分類の不正な鍵
+    #[test]
+    fn reject_invalid_conversation_keys() {
+        // 0、曲線の位数、それ以上の秘密鍵
+        for sec in [
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        ] {
+            assert!(Keys::parse(sec).is_err(), "{sec}");
+        }
+        // 0、体の標数p以上、曲線上にない公開鍵
+        let sec1 = Keys::parse(&format!("{:064x}", 1)).unwrap();
+        for pub2 in [
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "0000000000000000000000000000000000000000000000000000000000000005",
+        ] {
+            assert!(conversation_key(&sec1, pub2).is_err(), "{pub2}");
+        }
+    }
+
+    #[test]
+    fn vector_padded_len() {
+        for (len, padded) in [
+            (16, 32),
+            (32, 32),
+            (33, 64),
+            (37, 64),
+            (45, 64),
+            (49, 64),
+            (64, 64),
+            (65, 96),
+            (100, 128),
+            (111, 128),
+            (200, 224),
+            (250, 256),
+            (320, 320),
+            (383, 384),
+            (384, 384),
+            (400, 448),
+            (500, 512),
+            (512, 512),
+            (515, 640),
+            (700, 768),
+            (800, 896),
+            (900, 1024),
+            (1020, 1024),
+            (65536, 65536),
+        ] {
+            assert_eq!(calc_padded_len(len), padded, "len {len}");
+        }
+    }
+
+    #[test]
+    fn round_trip_random_nonce() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let key = conversation_key(&alice, &bob.public_key()).unwrap();
+        let message = "日本語のメッセージ".repeat(100);
+        let payload = encrypt(&key, &message).unwrap();
+        assert_ne!(encrypt(&key, &message).unwrap(), payload);
+        let key = conversation_key(&bob, &alice.public_key()).unwrap();
+        assert_eq!(decrypt(&key, &payload).unwrap(), message);
+    }
+
+    // 正しいMACを付けたまま、任意のパディング済みのデータを暗号化する
+    fn encrypt_padded(key: &[u8; 32], padded: &[u8]) -> String {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let nonce = [1u8; 32];
+        let keys = message_keys(key, &nonce).unwrap();
+        let mut ciphertext = padded.to_vec();
+        ChaCha20::new(&keys.chacha_key.into(), &keys.chacha_nonce.into())
+            .apply_keystream(&mut ciphertext);
+        let mac = hmac_aad(&keys.hmac_key, &nonce, &ciphertext).finalize();
+        let mut payload = vec![VERSION];
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&ciphertext);
+        payload.extend_from_slice(&mac.into_bytes());
+        STANDARD.encode(payload)
+    }
+
+    // 公式ベクトルのinvalid.decryptと同じ分類の、MACは正しいがパディングが不正なペイロード
+    #[test]
+    fn reject_invalid_padding() {
+        let key = key32("c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d");
+        let padded = |len: u16, body: &[u8], total: usize| {
+            let mut padded = len.to_be_bytes().to_vec();
+            padded.extend_from_slice(body);
+            padded.resize(total, 0);
+            padded
+        };
+        assert_eq!(
+            decrypt(&key, &encrypt_padded(&key, &padded(1, b"a", 34))).unwrap(),
+            "a"
+        );
+        for invalid in [
+            // 長さが0
+            padded(0, b"", 34),
+            // 長さが実際のデータより長い
+            padded(40, b"a", 34),
+            // パディング後の長さが規定と異なる
+            padded(1, b"a", 66),
+        ] {
+            assert!(decrypt(&key, &encrypt_padded(&key, &invalid)).is_err());
+        }
+    }
+
+    #[test]
+    fn reject_invalid_payloads() {
+        let key = key32("c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d");
+        let payload = "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb";
+
+        // MACの改ざん
+        let mut tampered = payload.to_string();
+        tampered.replace_range(100..101, "A");
+        assert!(decrypt(&key, &tampered).is_err());
+        // 未対応のバージョン
+        assert!(decrypt(&key, &format!("#{}", &payload[1..])).is_err());
+        assert!(decrypt(&key, &payload.replacen("Ag", "Aw", 1)).is_err());
+        // 短すぎるペイロード
+        assert!(decrypt(&key, "AgAA").is_err());
+        // base64として不正な文字
+        assert!(decrypt(&key, &payload.replacen('A', "!", 1)).is_err());
+        // 空の平文や長すぎる平文は暗号化できない
+        assert!(encrypt(&key, "").is_err());
+        assert!(encrypt(&key, &"a".repeat(65536)).is_err());
+        // パディングの長さが一致しない
+        let mut padded = vec![0, 1, b'a'];
+        padded.resize(2 + 64, 0);
+        assert!(unpad(&padded).is_err());
+    }
+}
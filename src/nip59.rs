@@ -0,0 +1,170 @@
+use rand::Rng;
+
+use crate::{
+    error::NostrError,
+    event::{unix_timestamp, Event, EventKind, UnsignedEvent},
+    keys::Keys,
+    nip44,
+};
+
+// 作成日時から送信時刻を推測されないように、最大2日前までの範囲でずらす
+const MAX_TIMESTAMP_SHIFT: i64 = 2 * 24 * 60 * 60;
+
+fn randomized_timestamp() -> i64 {
+    unix_timestamp() - rand::thread_rng().gen_range(0..MAX_TIMESTAMP_SHIFT)
+}
+
+// NIP-59: 署名のないイベント(rumor)を送信者の鍵で暗号化し、kind 13のsealに封入する
+// sealにはタグを付けず、受信者が誰かはsealからは分からない
+#[allow(dead_code)]
+pub fn seal(
+    keys: &Keys,
+    receiver_pubkey: &str,
+    rumor: &UnsignedEvent,
+) -> Result<Event, NostrError> {
+    if rumor.pubkey() != keys.public_key() {
+        return Err(NostrError::InvalidKey(
+            "rumor pubkey does not match the secret key".to_string(),
+        ));
+    }
+    let conversation_key = nip44::conversation_key(keys, receiver_pubkey)?;
+    let content = nip44::encrypt(&conversation_key, &serde_json::to_string(rumor).unwrap())?;
+    keys.sign_event(EventKind::SEAL, vec![], content, randomized_timestamp())
+}
+
+// sealを使い捨ての鍵で暗号化し、受信者の"p"タグを付けたkind 1059のgift wrapに包む
+#[allow(dead_code)]
+pub fn gift_wrap(seal: &Event, receiver_pubkey: &str) -> Result<Event, NostrError> {
+    let ephemeral = Keys::generate();
+    let conversation_key = nip44::conversation_key(&ephemeral, receiver_pubkey)?;
+    let content = nip44::encrypt(&conversation_key, &serde_json::to_string(seal).unwrap())?;
+    ephemeral.sign_event(
+        EventKind::GIFT_WRAP,
+        vec![vec!["p".to_string(), receiver_pubkey.to_string()]],
+        content,
+        randomized_timestamp(),
+    )
+}
+
+// rumorをsealしてからgift wrapする
+#[allow(dead_code)]
+pub fn wrap(
+    keys: &Keys,
+    receiver_pubkey: &str,
+    rumor: &UnsignedEvent,
+) -> Result<Event, NostrError> {
+    gift_wrap(&seal(keys, receiver_pubkey, rumor)?, receiver_pubkey)
+}
+
+// 受信者の鍵でgift wrapとsealを順に復号し、中のrumorを取り出す
+// sealの署名者とrumorの作成者が一致しない場合はなりすましとして拒否する
+#[allow(dead_code)]
+pub fn unwrap(keys: &Keys, gift_wrap: &Event) -> Result<UnsignedEvent, NostrError> {
+    if gift_wrap.kind != EventKind::GIFT_WRAP {
+        return Err(NostrError::InvalidMessage(
+            "expected a gift wrap event".to_string(),
+        ));
+    }
+    gift_wrap.verify()?;
+    let conversation_key = nip44::conversation_key(keys, &gift_wrap.pubkey)?;
+    let seal: Event = serde_json::from_str(&nip44::decrypt(&conversation_key, &gift_wrap.content)?)
+        .map_err(|e| NostrError::InvalidMessage(e.to_string()))?;
+
+    if seal.kind != EventKind::SEAL {
+        return Err(NostrError::InvalidMessage(
+            "expected a seal event".to_string(),
+        ));
+    }
+    seal.verify()?;
+    let conversation_key = nip44::conversation_key(keys, &seal.pubkey)?;
+    let rumor: UnsignedEvent =
+        serde_json::from_str(&nip44::decrypt(&conversation_key, &seal.content)?)
+            .map_err(|e| NostrError::InvalidMessage(e.to_string()))?;
+
+    rumor.verify_id()?;
+    if rumor.pubkey() != seal.pubkey {
+        return Err(NostrError::InvalidMessage(
+            "seal and rumor have different authors".to_string(),
+        ));
+    }
+    Ok(rumor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gift_wrap, seal, unwrap, wrap, MAX_TIMESTAMP_SHIFT};
+    use crate::{
+        event::{unix_timestamp, EventKind, UnsignedEvent},
+        keys::Keys,
+        nip44,
+        test_util::{alice, bob},
+    };
+
+    fn rumor(keys: &Keys, content: &str) -> UnsignedEvent {
+        UnsignedEvent::new(
+            keys.public_key(),
+            EventKind::TEXT_NOTE,
+            vec![],
+            content.to_string(),
+            unix_timestamp(),
+        )
+    }
+
+    #[test]
+    fn wrap_and_unwrap() {
+        let alice = alice();
+        let bob = bob();
+        let rumor = rumor(&alice, "Are you going to the party tonight?");
+        let wrapped = wrap(&alice, &bob.public_key(), &rumor).unwrap();
+
+        assert_eq!(wrapped.kind, EventKind::GIFT_WRAP);
+        assert!(wrapped.verify().is_ok());
+        assert_eq!(wrapped.tag_value("p"), Some(bob.public_key().as_str()));
+        // gift wrapは使い捨ての鍵で署名され、送信者は分からない
+        assert_ne!(wrapped.pubkey, alice.public_key());
+        let now = unix_timestamp();
+        assert!(wrapped.created_at <= now && wrapped.created_at > now - MAX_TIMESTAMP_SHIFT);
+
+        assert_eq!(unwrap(&bob, &wrapped).unwrap(), rumor);
+        // 受信者以外は復号できない
+        assert!(unwrap(&alice, &wrapped).is_err());
+    }
+
+    #[test]
+    fn seal_has_no_tags() {
+        let alice = alice();
+        let bob = bob();
+        let sealed = seal(&alice, &bob.public_key(), &rumor(&alice, "hi")).unwrap();
+        assert_eq!(sealed.kind, EventKind::SEAL);
+        assert_eq!(sealed.pubkey, alice.public_key());
+        assert!(sealed.tags.is_empty());
+        assert!(sealed.verify().is_ok());
+
+        // 他人が作成したrumorはsealしない
+        assert!(seal(&bob, &alice.public_key(), &rumor(&alice, "hi")).is_err());
+    }
+
+    #[test]
+    fn reject_impersonated_rumor() {
+        let alice = alice();
+        let bob = bob();
+        let eve = Keys::generate();
+
+        // eveがaliceの名前のrumorを自分の鍵でsealしても、作成者の不一致で拒否される
+        let forged = rumor(&alice, "I owe eve 100 sats");
+        let key = nip44::conversation_key(&eve, &bob.public_key()).unwrap();
+        let content = nip44::encrypt(&key, &serde_json::to_string(&forged).unwrap()).unwrap();
+        let sealed = eve
+            .sign_event(EventKind::SEAL, vec![], content, unix_timestamp())
+            .unwrap();
+        let wrapped = gift_wrap(&sealed, &bob.public_key()).unwrap();
+        assert!(unwrap(&bob, &wrapped).is_err());
+
+        // sealでないイベントをgift wrapしたものも拒否する
+        let note = eve
+            .sign_event(EventKind::TEXT_NOTE, vec![], "note".to_string(), 0)
+            .unwrap();
+        let wrapped = gift_wrap(&note, &bob.public_key()).unwrap();
+        assert!(unwrap(&bob, &wrapped).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Mutex,
+    thread,
+};
+
+use crate::{
+    error::NostrError,
+    event::{compute_id, Event, EventKind},
+};
+
+// IDは256ビットなので、これより高い難易度は満たせない
+const MAX_DIFFICULTY: u32 = 256;
+
+// NIP-13: イベントIDの先頭から連続する0のビット数
+pub fn difficulty(id: &str) -> u32 {
+    let mut bits = 0;
+    for c in id.chars() {
+        let Some(nibble) = c.to_digit(16) else {
+            break;
+        };
+        if nibble == 0 {
+            bits += 4;
+        } else {
+            // 4ビットのうち先頭の0の数
+            bits += nibble.leading_zeros() - 28;
+            break;
+        }
+    }
+    bits
+}
+
+// "nonce"タグの3番目の値で宣言された目標の難易度
+pub fn committed_target(tags: &[Vec<String>]) -> Option<u32> {
+    tags.iter()
+        .find(|t| t.first().is_some_and(|n| n == "nonce"))
+        .and_then(|t| t.get(2))
+        .and_then(|v| v.parse().ok())
+}
+
+// イベントの難易度
+// 目標を宣言している場合は、偶然それより高い難易度になっても宣言した目標を上限とする
+pub fn event_difficulty(event: &Event) -> u32 {
+    let difficulty = difficulty(&event.id);
+    match committed_target(&event.tags) {
+        Some(target) => difficulty.min(target),
+        None => difficulty,
+    }
+}
+
+// 指定した難易度を満たすまで"nonce"タグの値を変えながらIDを計算する
+// 複数のスレッドでnonceを分担し、見つかったタグとIDを返す
+// 満たすことのできない難易度の場合は探索しない
+pub fn mine(
+    pubkey: &str,
+    created_at: i64,
+    kind: EventKind,
+    tags: &[Vec<String>],
+    content: &str,
+    target: u32,
+    threads: usize,
+) -> Result<(Vec<Vec<String>>, String), NostrError> {
+    if target > MAX_DIFFICULTY {
+        return Err(NostrError::InvalidMessage(format!(
+            "difficulty {target} exceeds {MAX_DIFFICULTY} bits"
+        )));
+    }
+    // 既存のnonceタグは置き換える
+    let tags: Vec<Vec<String>> = tags
+        .iter()
+        .filter(|t| t.first().is_none_or(|n| n != "nonce"))
+        .cloned()
+        .collect();
+    let threads = threads.max(1) as u64;
+    let found = AtomicBool::new(false);
+    let result = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for start in 0..threads {
+            let (tags, found, result) = (&tags, &found, &result);
+            scope.spawn(move || {
+                let mut tags = tags.clone();
+                tags.push(vec!["nonce".to_string(), String::new(), target.to_string()]);
+                let last = tags.len() - 1;
+                let mut nonce = start;
+                while !found.load(Ordering::Relaxed) {
+                    tags[last][1] = nonce.to_string();
+                    let id = compute_id(pubkey, created_at, kind, &tags, content);
+                    if difficulty(&id) >= target {
+                        found.store(true, Ordering::Relaxed);
+                        result.lock().unwrap().get_or_insert((tags, id));
+                        return;
+                    }
+                    nonce += threads;
+                }
+            });
+        }
+    });
+
+    Ok(result.into_inner().unwrap().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{committed_target, difficulty, event_difficulty};
+    use crate::{
+        event::{EventKind, UnsignedEvent},
+        test_util::alice,
+    };
+
+    #[test]
+    fn count_leading_zero_bits() {
+        // NIP-13の例
+        assert_eq!(
+            difficulty("000000000e9d97a1ab09fc381030b346cdd7a142ad57e6df0b46dc9bef6c7e2d"),
+            36
+        );
+        assert_eq!(
+            difficulty("000006d8c378af1779d2feebc7603a125d99eca0ccf1085959b307f64e5dd358"),
+            21
+        );
+        assert_eq!(difficulty("f000"), 0);
+        assert_eq!(difficulty("7000"), 1);
+        assert_eq!(difficulty("0100"), 7);
+        assert_eq!(difficulty(&"0".repeat(64)), 256);
+    }
+
+    #[test]
+    fn read_committed_target() {
+        let tags = vec![vec![
+            "nonce".to_string(),
+            "776797".to_string(),
+            "20".to_string(),
+        ]];
+        assert_eq!(committed_target(&tags), Some(20));
+        assert_eq!(
+            committed_target(&[vec!["nonce".to_string(), "1".to_string()]]),
+            None
+        );
+    }
+
+    #[test]
+    fn mine_until_target_is_met() {
+        let keys = alice();
+        let event = UnsignedEvent::new(
+            keys.public_key(),
+            EventKind::TEXT_NOTE,
+            vec![vec!["nonce".to_string(), "0".to_string(), "1".to_string()]],
+            "It's just me mining my own business".to_string(),
+            1651794653,
+        )
+        .mine(12, 4)
+        .unwrap()
+        .sign_with_keys(&keys)
+        .unwrap();
+
+        assert!(event.verify().is_ok());
+        assert!(difficulty(&event.id) >= 12);
+        // nonceタグは1つだけで、目標を宣言している
+        let nonces: Vec<_> = event.tags.iter().filter(|t| t[0] == "nonce").collect();
+        assert_eq!(nonces.len(), 1);
+        assert_eq!(nonces[0][2], "12");
+        assert_eq!(event_difficulty(&event), 12);
+    }
+
+    #[test]
+    fn reject_unreachable_target() {
+        let keys = alice();
+        let event = UnsignedEvent::new(
+            keys.public_key(),
+            EventKind::TEXT_NOTE,
+            vec![],
+            "content".to_string(),
+            1651794653,
+        );
+        assert!(event.mine(257, 2).is_err());
+    }
+}
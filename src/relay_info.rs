@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::RelayConfig;
+
+// NIP-11のリレー情報ドキュメント
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayInformation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // 管理者の公開鍵 (32バイト) を小文字の16進数で表記
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    // 管理者の連絡先（URIなど）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    pub supported_nips: Vec<u16>,
+    pub software: String,
+    pub version: String,
+    pub limitation: Limitation,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub retention: Vec<Retention>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fees: Option<Fees>,
+}
+
+// リレーが課している制限
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Limitation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_message_length: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_subscriptions: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_pow_difficulty: Option<u8>,
+    pub auth_required: bool,
+    pub payment_required: bool,
+    pub restricted_writes: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at_upper_limit: Option<i64>,
+}
+
+// イベントの種類毎の保存期間・保存数
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Retention {
+    // 対象の種類。数値か[開始, 終了]の範囲で指定する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<KindRange>>,
+    // 保存期間（秒）。0の場合は保存しない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum KindRange {
+    Single(u16),
+    Range(u16, u16),
+}
+
+// 料金体系
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Fees {
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub admission: Vec<Fee>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub subscription: Vec<Fee>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub publication: Vec<Fee>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Fee {
+    pub amount: u64,
+    pub unit: String,
+    // 期間（秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<u16>>,
+}
+
+impl RelayInformation {
+    // リレーの設定からドキュメントを作成する
+    pub fn from_config(config: &RelayConfig) -> Self {
+        Self {
+            name: config.relay_name.clone(),
+            description: config.relay_description.clone(),
+            pubkey: config.relay_pubkey.clone(),
+            contact: config.relay_contact.clone(),
+            supported_nips: supported_nips(config),
+            software: env!("CARGO_PKG_REPOSITORY").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            limitation: Limitation {
+                min_pow_difficulty: (config.min_pow_difficulty > 0)
+                    .then_some(config.min_pow_difficulty),
+                auth_required: config.auth_required,
+                restricted_writes: !config.blocked_pubkeys.is_empty()
+                    || !config.allowed_pubkeys.is_empty(),
+                created_at_upper_limit: Some(config.max_future_seconds),
+                ..Default::default()
+            },
+            retention: config.retention.clone(),
+            fees: config.fees.clone(),
+        }
+    }
+}
+
+// リレーが対応しているNIPの番号
+fn supported_nips(config: &RelayConfig) -> Vec<u16> {
+    let mut nips = vec![1, 9, 11, 13, 17, 26, 40, 42, 45, 59, 70];
+    // 全文検索はインデックスを作成する場合のみ対応
+    if config.search_enabled {
+        nips.push(50);
+    }
+    nips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fee, Fees, KindRange, RelayInformation, Retention};
+    use crate::config::RelayConfig;
+
+    #[test]
+    fn serialize_from_config() {
+        let config = RelayConfig {
+            relay_name: Some("relay".to_string()),
+            relay_contact: Some("mailto:admin@example.com".to_string()),
+            retention: vec![Retention {
+                kinds: Some(vec![KindRange::Single(0), KindRange::Range(40, 49)]),
+                time: Some(3600),
+                count: None,
+            }],
+            fees: Some(Fees {
+                admission: vec![Fee {
+                    amount: 1000000,
+                    unit: "msats".to_string(),
+                    period: None,
+                    kinds: None,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let info = RelayInformation::from_config(&config);
+        let expected = format!(
+            r#"{{"name":"relay","contact":"mailto:admin@example.com","supported_nips":[1,9,11,13,17,26,40,42,45,59,70],"software":"{}","version":"{}","limitation":{{"auth_required":false,"payment_required":false,"restricted_writes":false,"created_at_upper_limit":900}},"retention":[{{"kinds":[0,[40,49]],"time":3600}}],"fees":{{"admission":[{{"amount":1000000,"unit":"msats"}}]}}}}"#,
+            env!("CARGO_PKG_REPOSITORY"),
+            env!("CARGO_PKG_VERSION"),
+        );
+        assert_eq!(serde_json::to_string(&info).unwrap(), expected);
+    }
+
+    #[test]
+    fn advertise_search_only_when_enabled() {
+        let info = RelayInformation::from_config(&RelayConfig::default());
+        assert!(!info.supported_nips.contains(&50));
+        let config = RelayConfig {
+            search_enabled: true,
+            ..Default::default()
+        };
+        let info = RelayInformation::from_config(&config);
+        assert!(info.supported_nips.contains(&50));
+    }
+}
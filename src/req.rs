@@ -1,4 +1,11 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+use serde::{
+    de::{IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{event::Event, pow::event_difficulty, search};
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Req {
@@ -6,7 +13,21 @@ pub struct Req {
     pub filter: Vec<Filter>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+impl Req {
+    // クライアントからのREQ・COUNTでは、委任されたイベントも委任者の公開鍵で検索できるようにする
+    pub fn include_delegated(self) -> Self {
+        Self {
+            filter: self
+                .filter
+                .into_iter()
+                .map(Filter::include_delegated)
+                .collect(),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Filter {
     // イベントのID、もしくは先頭部分（プレフィクス）のリスト
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -17,12 +38,10 @@ pub struct Filter {
     // イベントの種類の数字のリスト
     #[serde(skip_serializing_if = "Option::is_none")]
     pub kinds: Option<Vec<u16>>,
-    // "e"タグで参照されたイベントIDのリスト
-    #[serde(rename = "#e", skip_serializing_if = "Option::is_none")]
-    pub e_tags: Option<Vec<String>>,
-    // "p"タグで参照された公開鍵のリスト
-    #[serde(rename = "#p", skip_serializing_if = "Option::is_none")]
-    pub p_tags: Option<Vec<String>>,
+    // "#<英字1文字>"で指定されたタグの値のリスト
+    // 例えば"#e"は"e"タグで参照されたイベントID、"#p"は"p"タグで参照された公開鍵のリスト
+    #[serde(flatten, with = "tag_filters")]
+    pub tags: BTreeMap<char, Vec<String>>,
     // UNIXタイムスタンプ（秒単位の整数値）。パスするには、イベントはこれより新しくなければならない
     #[serde(skip_serializing_if = "Option::is_none")]
     pub since: Option<i64>,
@@ -32,8 +51,19 @@ pub struct Filter {
     // 初回の問い合わせで返されるイベントの個数の上限
     #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<usize>,
+    // NIP-50: 内容の全文検索のクエリ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    // NIP-13の難易度の下限。パスするには、イベントはこれ以上の難易度でなければならない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_pow: Option<u32>,
+    // NIP-26: authorsを委任者の公開鍵とも照合するかどうか
+    // リレー内部での作成者の確認ではpubkeyのみと照合するため、クライアントからのフィルタでのみ有効にする
+    #[serde(skip)]
+    pub delegated: bool,
 }
 
+#[allow(dead_code)]
 impl Filter {
     // 新しいクエリインスタンスを生成するためのコンストラクタ
     pub fn new() -> Self {
@@ -41,11 +71,13 @@ impl Filter {
             ids: None,
             authors: None,
             kinds: None,
-            e_tags: None,
-            p_tags: None,
+            tags: BTreeMap::new(),
             since: None,
             until: None,
             limit: None,
+            search: None,
+            min_pow: None,
+            delegated: false,
         }
     }
 
@@ -65,14 +97,17 @@ impl Filter {
         self
     }
 
-    pub fn e_tags(mut self, e_tags: Vec<String>) -> Self {
-        self.e_tags = Some(e_tags);
+    pub fn tag(mut self, name: char, values: Vec<String>) -> Self {
+        self.tags.insert(name, values);
         self
     }
 
-    pub fn p_tags(mut self, p_tags: Vec<String>) -> Self {
-        self.p_tags = Some(p_tags);
-        self
+    pub fn e_tags(self, e_tags: Vec<String>) -> Self {
+        self.tag('e', e_tags)
+    }
+
+    pub fn p_tags(self, p_tags: Vec<String>) -> Self {
+        self.tag('p', p_tags)
     }
 
     pub fn since(mut self, since: i64) -> Self {
@@ -89,4 +124,190 @@ impl Filter {
         self.limit = Some(limit);
         self
     }
+
+    pub fn search(mut self, search: &str) -> Self {
+        self.search = Some(search.to_string());
+        self
+    }
+
+    pub fn min_pow(mut self, min_pow: u32) -> Self {
+        self.min_pow = Some(min_pow);
+        self
+    }
+
+    pub fn include_delegated(mut self) -> Self {
+        self.delegated = true;
+        self
+    }
+}
+
+impl Filter {
+    // イベントがフィルタのすべての条件に合致するかどうか
+    pub fn match_event(&self, event: &Event) -> bool {
+        contains(self.ids.as_ref(), &event.id)
+            && (contains(self.authors.as_ref(), &event.pubkey)
+                || self.delegated
+                    && self
+                        .authors
+                        .as_ref()
+                        .zip(event.delegator())
+                        .is_some_and(|(authors, delegator)| authors.iter().any(|a| a == delegator)))
+            && contains(self.kinds.as_ref(), &u16::from(event.kind))
+            && self
+                .tags
+                .iter()
+                .all(|(name, values)| match_tag(*name, values, event))
+            && self.since.is_none_or(|since| since <= event.created_at)
+            && self.until.is_none_or(|until| event.created_at <= until)
+            && self
+                .search
+                .as_deref()
+                .is_none_or(|query| search::matches(query, &event.content))
+            && self
+                .min_pow
+                .is_none_or(|min_pow| event_difficulty(event) >= min_pow)
+    }
+}
+
+fn contains<T>(vec: Option<&Vec<T>>, item: &T) -> bool
+where
+    T: PartialEq,
+{
+    // フィルタが指定されていない場合は、常にtrueを返す
+    vec.is_none_or(|v| v.contains(item))
+}
+
+// タグ名が一致し、最初の値が指定された値のいずれかであるタグを持つかどうか
+fn match_tag(name: char, values: &[String], event: &Event) -> bool {
+    let name = name.to_string();
+    event
+        .tags
+        .iter()
+        .any(|t| t.first() == Some(&name) && t.get(1).is_some_and(|v| values.contains(v)))
+}
+
+// フィルタの"#<英字1文字>"のキーとタグ名の対応付けを行う
+mod tag_filters {
+    use super::*;
+
+    pub fn serialize<S>(
+        tags: &BTreeMap<char, Vec<String>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            tags.iter()
+                .map(|(name, values)| (format!("#{}", name), values)),
+        )
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BTreeMap<char, Vec<String>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(TagFiltersVisitor)
+    }
+
+    struct TagFiltersVisitor;
+
+    impl<'de> Visitor<'de> for TagFiltersVisitor {
+        type Value = BTreeMap<char, Vec<String>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map of single-letter tag filters")
+        }
+
+        fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+        where
+            M: MapAccess<'de>,
+        {
+            let mut tags = BTreeMap::new();
+            while let Some(key) = map.next_key::<String>()? {
+                match tag_name(&key) {
+                    Some(name) => {
+                        tags.insert(name, map.next_value::<Vec<String>>()?);
+                    }
+                    // 未知のキーは無視する
+                    None => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+            }
+            Ok(tags)
+        }
+    }
+
+    // "#"と英字1文字からなるキーであればタグ名を返す
+    fn tag_name(key: &str) -> Option<char> {
+        let mut chars = key.strip_prefix('#')?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use crate::test_util::{signed_event, ALICE};
+
+    #[test]
+    fn deserialize_generic_tags() {
+        let filter: Filter = serde_json::from_str(
+            r##"{"kinds":[30023],"#d":["article"],"#t":["nostr","rust"],"#long":["x"],"unknown":1}"##,
+        )
+        .unwrap();
+        let expected = Filter::new()
+            .kinds(vec![30023])
+            .tag('d', vec!["article".to_string()])
+            .tag('t', vec!["nostr".to_string(), "rust".to_string()]);
+        assert_eq!(filter, expected);
+        assert_eq!(
+            serde_json::to_string(&filter).unwrap(),
+            r##"{"kinds":[30023],"#d":["article"],"#t":["nostr","rust"]}"##
+        );
+    }
+
+    #[test]
+    fn match_tag_name_and_first_value() {
+        let event = signed_event(
+            ALICE,
+            1,
+            vec![vec!["t", "nostr"], vec!["r", "wss://relay", "rust"]],
+            "content",
+            1700000000,
+        );
+        assert!(Filter::new()
+            .tag('t', vec!["nostr".to_string()])
+            .match_event(&event));
+        assert!(Filter::new()
+            .tag('r', vec!["wss://relay".to_string()])
+            .match_event(&event));
+        // 2番目以降の値や別のタグ名の値には合致しない
+        assert!(!Filter::new()
+            .tag('r', vec!["rust".to_string()])
+            .match_event(&event));
+        assert!(!Filter::new()
+            .tag('e', vec!["nostr".to_string()])
+            .match_event(&event));
+        // 複数のタグ条件はすべて満たす必要がある
+        assert!(!Filter::new()
+            .tag('t', vec!["nostr".to_string()])
+            .tag('d', vec!["x".to_string()])
+            .match_event(&event));
+    }
+
+    #[test]
+    fn match_min_pow() {
+        let event = signed_event(ALICE, 1, vec![], "content", 1700000000);
+        let difficulty = crate::pow::difficulty(&event.id);
+        assert!(Filter::new().min_pow(difficulty).match_event(&event));
+        assert!(!Filter::new().min_pow(difficulty + 1).match_event(&event));
+
+        let filter: Filter = serde_json::from_str(r##"{"kinds":[1],"min_pow":20}"##).unwrap();
+        assert_eq!(filter, Filter::new().kinds(vec![1]).min_pow(20));
+    }
 }
@@ -0,0 +1,213 @@
+use std::collections::{HashMap, HashSet};
+
+// BM25のパラメータ
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+// 文字列を検索用のトークンに分割する
+// 英数字の連続は小文字にした単語とし、日本語などの分かち書きしない文字は2文字ずつ（bi-gram）に区切る
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut cjk = Vec::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if is_cjk(c) {
+            push_word(&mut tokens, &mut word);
+            cjk.push(c);
+        } else if c.is_alphanumeric() {
+            push_bigrams(&mut tokens, &mut cjk);
+            word.push(c);
+        } else {
+            push_word(&mut tokens, &mut word);
+            push_bigrams(&mut tokens, &mut cjk);
+        }
+    }
+    push_word(&mut tokens, &mut word);
+    push_bigrams(&mut tokens, &mut cjk);
+    tokens
+}
+
+fn push_word(tokens: &mut Vec<String>, word: &mut String) {
+    if !word.is_empty() {
+        tokens.push(std::mem::take(word));
+    }
+}
+
+fn push_bigrams(tokens: &mut Vec<String>, chars: &mut Vec<char>) {
+    match chars.len() {
+        0 => {}
+        1 => tokens.push(chars[0].to_string()),
+        _ => tokens.extend(chars.windows(2).map(|w| w.iter().collect())),
+    }
+    chars.clear();
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // ひらがな・カタカナ
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}' // 漢字
+        | '\u{ac00}'..='\u{d7af}' // ハングル
+        | '\u{ff66}'..='\u{ff9f}') // 半角カタカナ
+}
+
+// 検索クエリの単語
+// NIP-50の"key:value"形式の拡張には対応していないので無視する
+pub fn query_terms(query: &str) -> Vec<String> {
+    let terms: HashSet<String> = query
+        .split_whitespace()
+        .filter(|w| !w.contains(':'))
+        .flat_map(tokenize)
+        .collect();
+    terms.into_iter().collect()
+}
+
+// 検索クエリのすべての単語を含むかどうか
+pub fn matches(query: &str, content: &str) -> bool {
+    let terms = query_terms(query);
+    if terms.is_empty() {
+        return true;
+    }
+    let tokens: HashSet<String> = tokenize(content).into_iter().collect();
+    terms.iter().all(|t| tokens.contains(t))
+}
+
+// イベントの内容の全文検索用の転置インデックス
+#[derive(Default)]
+pub struct SearchIndex {
+    // 単語毎の、その単語を含むイベントのIDと出現回数
+    postings: HashMap<String, HashMap<String, u32>>,
+    // イベント毎の単語（重複を含む）
+    documents: HashMap<String, Vec<String>>,
+    total_length: usize,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, id: &str, content: &str) {
+        if self.documents.contains_key(id) {
+            return;
+        }
+        let tokens = tokenize(content);
+        self.total_length += tokens.len();
+        for token in &tokens {
+            *self
+                .postings
+                .entry(token.clone())
+                .or_default()
+                .entry(id.to_string())
+                .or_default() += 1;
+        }
+        self.documents.insert(id.to_string(), tokens);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(tokens) = self.documents.remove(id) else {
+            return;
+        };
+        self.total_length -= tokens.len();
+        for token in tokens {
+            if let Some(ids) = self.postings.get_mut(&token) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+    }
+
+    // すべての単語を含むイベントのIDを、BM25のスコアの高い順に返す
+    pub fn search(&self, terms: &[String]) -> Vec<(String, f64)> {
+        let Some(postings) = terms
+            .iter()
+            .map(|t| self.postings.get(t))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Vec::new();
+        };
+        let Some(rarest) = postings.iter().min_by_key(|p| p.len()) else {
+            return Vec::new();
+        };
+
+        let documents = self.documents.len() as f64;
+        let average_length = self.total_length as f64 / documents;
+        let mut hits: Vec<(String, f64)> = rarest
+            .keys()
+            .filter(|id| postings.iter().all(|p| p.contains_key(*id)))
+            .map(|id| {
+                let length = self.documents[id].len() as f64;
+                let score = postings
+                    .iter()
+                    .map(|p| {
+                        let df = p.len() as f64;
+                        let idf = ((documents - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        let tf = p[id] as f64;
+                        idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average_length))
+                    })
+                    .sum();
+                (id.clone(), score)
+            })
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, query_terms, tokenize, SearchIndex};
+
+    #[test]
+    fn tokenize_words_and_bigrams() {
+        assert_eq!(
+            tokenize("Hello, Nostr-relay! 2024"),
+            vec!["hello", "nostr", "relay", "2024"]
+        );
+        assert_eq!(
+            tokenize("Rustで日本語"),
+            vec!["rust", "で日", "日本", "本語"]
+        );
+        assert_eq!(tokenize("猫"), vec!["猫"]);
+    }
+
+    #[test]
+    fn ignore_extensions_in_query() {
+        let mut terms = query_terms("Nostr include:spam relay");
+        terms.sort();
+        assert_eq!(terms, vec!["nostr", "relay"]);
+    }
+
+    #[test]
+    fn match_all_terms_case_insensitive() {
+        assert!(matches("NOSTR relay", "a relay for nostr"));
+        assert!(!matches("nostr relay", "nostr client"));
+        assert!(matches("日本語", "日本語の投稿"));
+        assert!(!matches("日本語", "日本の投稿"));
+        assert!(matches("", "anything"));
+    }
+
+    #[test]
+    fn rank_by_relevance() {
+        let mut index = SearchIndex::new();
+        index.add("a", "nostr nostr nostr relay");
+        index.add(
+            "b",
+            "a long note that mentions nostr only once among many other words",
+        );
+        index.add("c", "unrelated");
+        let hits = index.search(&query_terms("nostr"));
+        assert_eq!(
+            hits.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert!(index.search(&query_terms("nostr missing")).is_empty());
+
+        index.remove("a");
+        let hits = index.search(&query_terms("nostr"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0, "b");
+    }
+}
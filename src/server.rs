@@ -3,16 +3,17 @@ use axum::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         State,
     },
-    response::IntoResponse,
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
     routing::get,
     Router,
 };
 use axum_extra::TypedHeader;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::{ops::ControlFlow, sync::Arc};
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use std::{ops::ControlFlow, sync::Arc, time::Duration};
+use tokio::sync::{Mutex, RwLock};
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 
 use axum::extract::connect_info::ConnectInfo;
@@ -20,11 +21,22 @@ use axum::extract::connect_info::ConnectInfo;
 use futures::{stream::StreamExt, SinkExt};
 
 use crate::{
+    auth::{authorize, authorize_gift_wraps, authorize_protected, can_receive, verify_auth_event},
+    config::{RelayConfig, StoreBackend},
+    connection::{AuthedPubkeys, Connection},
+    deletion::{apply_deletion, is_deleted},
     error::NostrError,
-    event::Event,
-    message::{ClientMessage, ServerMessage, ServerOk},
+    event::{unix_timestamp, Event, EventKind},
+    fanout::fanout,
+    message::{
+        ClientMessage, Closed, ReasonPrefix, ServerCount, ServerMessage, ServerMessageEvent,
+        ServerOk,
+    },
+    relay_info::RelayInformation,
     req::{Filter, Req},
+    store::{sort_newest_first, EventStore, IndexedStore, MemoryStore, SaveOutcome, SqliteStore},
     subscriber::Subscriber,
+    validation::{validate_event, RateLimiter, Rejection},
 };
 
 #[derive(Clone)]
@@ -33,15 +45,42 @@ struct RelayState {
     // 接続毎に複数のサブスクライバーを登録可能
     // HashMapのkeyはクライアントのアドレス
     subscribers: Arc<RwLock<HashMap<String, Vec<Subscriber>>>>,
+    // 接続毎のイベント数の制限
+    // HashMapのkeyはクライアントのアドレス
+    rate_limiters: Arc<Mutex<HashMap<String, RateLimiter>>>,
+    // 受信したイベントの保存先
+    store: Arc<dyn EventStore>,
+    config: Arc<RelayConfig>,
+}
+
+impl RelayState {
+    fn new(config: RelayConfig, store: Arc<dyn EventStore>) -> Self {
+        Self {
+            subscribers: Arc::new(RwLock::new(HashMap::new())),
+            rate_limiters: Arc::new(Mutex::new(HashMap::new())),
+            store,
+            config: Arc::new(config),
+        }
+    }
 }
 
-pub async fn serve() {
-    let state = RelayState {
-        subscribers: Arc::new(RwLock::new(HashMap::new())),
+pub async fn serve(config: RelayConfig) {
+    let store: Box<dyn EventStore> = match &config.store {
+        StoreBackend::Memory => Box::new(MemoryStore::new()),
+        StoreBackend::Sqlite(path) => {
+            Box::new(SqliteStore::open(path).expect("failed to open database"))
+        }
+    };
+    let store: Arc<dyn EventStore> = if config.search_enabled {
+        Arc::new(IndexedStore::new(store).expect("failed to build search index"))
+    } else {
+        Arc::from(store)
     };
+    let state = RelayState::new(config, store);
+    spawn_expiration_purge(&state);
 
     let app = Router::new()
-        .route("/", get(ws_handler))
+        .route("/", get(ws_handler).options(preflight_handler))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
@@ -60,12 +99,43 @@ pub async fn serve() {
     .unwrap();
 }
 
+// 有効期限切れのイベントを定期的に削除するタスクを開始
+fn spawn_expiration_purge(state: &RelayState) {
+    let store = state.store.clone();
+    let period = Duration::from_secs(state.config.expiration_purge_interval.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match run_blocking(&store, |store| store.purge_expired(unix_timestamp())).await {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("purged {} expired events", n),
+                Err(e) => tracing::error!("{}", e.to_string()),
+            }
+        }
+    });
+}
+
 async fn ws_handler(
-    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    ws: Option<WebSocketUpgrade>,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<RelayState>,
-) -> impl IntoResponse {
+) -> Response {
+    // NIP-11: Acceptヘッダーでリレー情報ドキュメントが要求された場合はWebSocketに切り替えずに返す
+    let accepts_info = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains(RELAY_INFO_CONTENT_TYPE));
+    if accepts_info {
+        return relay_info_handler(&state);
+    }
+    let Some(ws) = ws else {
+        return "Please use a Nostr client to connect.".into_response();
+    };
+
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
     } else {
@@ -73,18 +143,71 @@ async fn ws_handler(
     };
     println!("`{user_agent}` at {addr} connected.");
 
-    ws.on_upgrade(move |socket| handle_socket(socket, state, addr))
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, addr, host))
+}
+
+const RELAY_INFO_CONTENT_TYPE: &str = "application/nostr+json";
+
+fn relay_info_handler(state: &RelayState) -> Response {
+    let info = RelayInformation::from_config(&state.config);
+    let mut response = (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(RELAY_INFO_CONTENT_TYPE),
+        )],
+        serde_json::to_string(&info).unwrap(),
+    )
+        .into_response();
+    add_cors_headers(response.headers_mut());
+    response
+}
+
+// ブラウザからリレー情報ドキュメントを取得するためのCORSのプリフライトリクエスト
+async fn preflight_handler() -> Response {
+    let mut response = ().into_response();
+    add_cors_headers(response.headers_mut());
+    response
+}
+
+fn add_cors_headers(headers: &mut HeaderMap) {
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("GET, OPTIONS"),
+    );
 }
 
-async fn handle_socket(socket: WebSocket, state: RelayState, who: SocketAddr) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: RelayState,
+    who: SocketAddr,
+    host: Option<String>,
+) {
     let (mut sock_tx, mut sock_rx) = socket.split();
     // socketのsenderにメッセージを送信するためのチャンネル
     // socketのsenderを使って複数箇所から送信を行うのが難しいのでチャネルを経由させる
     let (message_tx, mut message_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let conn = Arc::new(Connection::new(who, message_tx).with_host(host));
+
+    // 認証を必須とする場合は接続時にチャレンジを送信
+    if state.config.auth_required {
+        conn.send(&ServerMessage::Auth(conn.challenge.clone()));
+    }
 
     if let Some(msg) = sock_rx.next().await {
         if let Ok(msg) = msg {
-            if process_message(msg, state.clone(), who, message_tx.clone())
+            if process_message(msg, state.clone(), conn.clone())
                 .await
                 .is_break()
             {
@@ -97,17 +220,17 @@ async fn handle_socket(socket: WebSocket, state: RelayState, who: SocketAddr) {
     }
 
     // メッセージ送信用タスクを開始
-    let _ = tokio::spawn(async move {
+    tokio::spawn(async move {
         while let Some(msg) = message_rx.recv().await {
             let _ = sock_tx.send(msg).await;
         }
     });
 
     // メッセージ受信用タスクを開始
-    let _ = tokio::spawn(async move {
+    tokio::spawn(async move {
         while let Some(Ok(msg)) = sock_rx.next().await {
             // print message and break if instructed to do so
-            if process_message(msg, state.clone(), who, message_tx.clone())
+            if process_message(msg, state.clone(), conn.clone())
                 .await
                 .is_break()
             {
@@ -115,26 +238,27 @@ async fn handle_socket(socket: WebSocket, state: RelayState, who: SocketAddr) {
             }
         }
         state.subscribers.write().await.remove(&who.to_string());
+        state.rate_limiters.lock().await.remove(&who.to_string());
     });
 }
 
 async fn process_message(
     msg: Message,
     state: RelayState,
-    who: SocketAddr,
-    message_sender: UnboundedSender<Message>,
+    conn: Arc<Connection>,
 ) -> ControlFlow<(), ()> {
+    let who = conn.who;
     match msg {
         Message::Text(t) => {
             println!(">>> {who} sent str: {t:?}");
-            match process_nostr_message(t, state, who, message_sender).await {
-                Ok(_) => return ControlFlow::Continue(()),
+            match process_nostr_message(t, state, &conn).await {
+                Ok(_) => ControlFlow::Continue(()),
                 Err(e) => {
                     println!(">>> {who} sent invalid message: {e}");
                     tracing::error!("{}", e.to_string());
-                    return ControlFlow::Continue(());
+                    ControlFlow::Continue(())
                 }
-            };
+            }
         }
         Message::Close(c) => {
             if let Some(cf) = c {
@@ -145,7 +269,7 @@ async fn process_message(
             } else {
                 println!(">>> {who} somehow sent close message without CloseFrame");
             }
-            return ControlFlow::Break(());
+            ControlFlow::Break(())
         }
         _ => ControlFlow::Continue(()),
     }
@@ -155,109 +279,866 @@ async fn process_message(
 async fn process_nostr_message(
     message: String,
     state: RelayState,
-    who: SocketAddr,
-    message_sender: UnboundedSender<Message>,
+    conn: &Connection,
 ) -> Result<(), NostrError> {
     let message: ClientMessage =
         serde_json::from_str(&message).map_err(|e| NostrError::InvalidMessage(e.to_string()))?;
 
     match message {
-        ClientMessage::Req(req) => process_req_message(req, state, who, message_sender).await,
-        ClientMessage::Event(event) => process_event_message(event, state, message_sender).await,
-        ClientMessage::Close(id) => process_close_message(id, state, who).await,
+        ClientMessage::Req(req) => process_req_message(req.include_delegated(), state, conn).await,
+        ClientMessage::Event(event) => process_event_message(event, state, conn).await,
+        ClientMessage::Close(id) => process_close_message(id, state, conn).await,
+        ClientMessage::Auth(event) => process_auth_message(event, state, conn).await,
+        ClientMessage::Count(req) => {
+            process_count_message(req.include_delegated(), state, conn).await
+        }
     }
 }
 
 async fn process_req_message(
     req: Req,
     state: RelayState,
-    who: SocketAddr,
-    message_sender: UnboundedSender<Message>,
+    conn: &Connection,
 ) -> Result<(), NostrError> {
+    // リレーのポリシーで認証が必要な場合は理由付きのCLOSEDメッセージを返す
+    if let Err(rejection) =
+        authorize(conn, &state.config).and_then(|_| authorize_gift_wraps(conn, &req.filter))
+    {
+        send_closed(conn, &state, req.id, rejection);
+        return Ok(());
+    }
+
+    // 保存済みイベントの送信中に届いたイベントを取りこぼさないように、
+    // 書き込みロックを保持したまま検索・送信・登録を行う
+    // (イベントの保存と配信は読み込みロックを保持したまま行われる)
+    let mut subscribers = state.subscribers.write().await;
+
+    // gift wrapは受信者として認証した接続にのみ送信する
+    let authed_pubkeys = conn.shared_authed_pubkeys();
+    let filters = req.filter.clone();
+    let authed = authed_pubkeys.clone();
+    let queried = run_blocking(&state.store, move |store| {
+        query_receivable(store, &filters, &authed)
+    })
+    .await;
+    let events = match queried {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            conn.send(&ServerMessage::Closed(Closed {
+                subscribe_id: req.id,
+                message: ReasonPrefix::Error.with_message("could not query events"),
+            }));
+            return Ok(());
+        }
+    };
+    for event in events {
+        conn.send(&ServerMessage::Event(ServerMessageEvent {
+            subscribe_id: req.id.clone(),
+            event,
+        }));
+    }
+    conn.send(&ServerMessage::EOSE(req.id.clone()));
+
     // サブスクリプション登録
-    state
-        .subscribers
-        .write()
-        .await
-        .entry(who.to_string())
-        .or_insert(Vec::new())
-        .push(Subscriber {
-            client: who.to_string(),
-            sender: message_sender,
-            id: req.id,
-            filter: req.filter,
-        });
+    // 同じIDのサブスクリプションが既にある場合は置き換える
+    let client_subscribers = subscribers.entry(conn.who.to_string()).or_default();
+    client_subscribers.retain(|s| s.id != req.id);
+    client_subscribers.push(Subscriber {
+        client: conn.who.to_string(),
+        sender: conn.sender.clone(),
+        id: req.id,
+        filter: req.filter,
+        authed_pubkeys,
+    });
+
+    Ok(())
+}
+
+async fn process_count_message(
+    req: Req,
+    state: RelayState,
+    conn: &Connection,
+) -> Result<(), NostrError> {
+    if let Err(rejection) =
+        authorize(conn, &state.config).and_then(|_| authorize_gift_wraps(conn, &req.filter))
+    {
+        send_closed(conn, &state, req.id, rejection);
+        return Ok(());
+    }
 
+    // ストアから正確な件数を数えるため、approximateは常にfalse
+    let filters = req.filter.clone();
+    let authed_pubkeys = conn.shared_authed_pubkeys();
+    let counted = run_blocking(&state.store, move |store| {
+        count_receivable(store, &filters, &authed_pubkeys)
+    })
+    .await;
+    match counted {
+        Ok(count) => conn.send(&ServerMessage::Count(ServerCount {
+            subscribe_id: req.id,
+            count: count as u64,
+            approximate: false,
+        })),
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            conn.send(&ServerMessage::Closed(Closed {
+                subscribe_id: req.id,
+                message: ReasonPrefix::Error.with_message("could not count events"),
+            }));
+        }
+    }
     Ok(())
 }
 
+// 接続が受け取れるイベントだけを返す
+// 受け取れないgift wrapがlimitの枠を使わないように、除いた場合はフィルタ毎にlimitなしで検索し直す
+fn query_receivable(
+    store: &dyn EventStore,
+    filters: &[Filter],
+    authed_pubkeys: &AuthedPubkeys,
+) -> Result<Vec<Event>, NostrError> {
+    let events = store.query(filters)?;
+    if events.iter().all(|e| can_receive(authed_pubkeys, e)) {
+        return Ok(events);
+    }
+
+    let mut seen = HashSet::new();
+    let mut events = Vec::new();
+    for filter in filters {
+        let unlimited = Filter {
+            limit: None,
+            ..filter.clone()
+        };
+        for event in store
+            .query(&[unlimited])?
+            .into_iter()
+            .filter(|e| can_receive(authed_pubkeys, e))
+            .take(filter.limit.unwrap_or(usize::MAX))
+        {
+            if seen.insert(event.id.clone()) {
+                events.push(event);
+            }
+        }
+    }
+    // フィルタが1つの場合はストアの順序（全文検索の関連度順など）のまま返す
+    if filters.len() > 1 {
+        sort_newest_first(&mut events);
+    }
+    Ok(events)
+}
+
+// gift wrapを含みうる場合は、接続が受け取れるイベントだけを数える
+// 合致するgift wrapの件数を除き、認証済みの公開鍵宛てのものだけを"#p"で絞り込んで数え直す
+fn count_receivable(
+    store: &dyn EventStore,
+    filters: &[Filter],
+    authed_pubkeys: &AuthedPubkeys,
+) -> Result<usize, NostrError> {
+    let gift_wrap = u16::from(EventKind::GIFT_WRAP);
+    let gift_wrap_filters: Vec<Filter> = filters
+        .iter()
+        .filter(|f| {
+            f.kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.contains(&gift_wrap))
+        })
+        .map(|f| Filter {
+            kinds: Some(vec![gift_wrap]),
+            ..f.clone()
+        })
+        .collect();
+    if gift_wrap_filters.is_empty() {
+        return store.count(filters);
+    }
+
+    let authed = authed_pubkeys.to_vec();
+    let receivable_filters: Vec<Filter> = gift_wrap_filters
+        .iter()
+        .filter_map(|f| {
+            // gift wrapの"p"タグは受信者1人だけなので、既に"#p"がある場合は重なる公開鍵に絞る
+            let recipients: Vec<String> = match f.tags.get(&'p') {
+                Some(values) => values
+                    .iter()
+                    .filter(|v| authed.contains(v))
+                    .cloned()
+                    .collect(),
+                None => authed.clone(),
+            };
+            (!recipients.is_empty()).then(|| f.clone().p_tags(recipients))
+        })
+        .collect();
+    // 数える間に保存されたイベントで負にならないようにする
+    Ok((store.count(filters)? + store.count(&receivable_filters)?)
+        .saturating_sub(store.count(&gift_wrap_filters)?))
+}
+
+// 理由付きのCLOSEDメッセージを返す
+// 認証を必須としていないリレーでも、認証を求める場合は改めてチャレンジを送る
+fn send_closed(conn: &Connection, state: &RelayState, subscribe_id: String, rejection: Rejection) {
+    if rejection.prefix == ReasonPrefix::AuthRequired && !state.config.auth_required {
+        conn.send(&ServerMessage::Auth(conn.challenge.clone()));
+    }
+    conn.send(&ServerMessage::Closed(Closed {
+        subscribe_id,
+        message: rejection.prefix.with_message(&rejection.message),
+    }));
+}
+
 async fn process_event_message(
     event: Event,
     state: RelayState,
-    message_sender: UnboundedSender<Message>,
+    conn: &Connection,
 ) -> Result<(), NostrError> {
-    // OKメッセージを送信
-    let _ = message_sender.send(Message::Text(
-        serde_json::to_string(&ServerMessage::Ok(ServerOk {
-            event_id: event.id.clone(),
-            accepted: true,
-            message: "".to_string(),
-        }))
-        .unwrap(),
-    ));
-
-    for s in state
-        .subscribers
-        .read()
+    // イベントを検証し、受け付けない場合は理由付きのOKメッセージを返す
+    let now = unix_timestamp();
+    let validated = state
+        .rate_limiters
+        .lock()
         .await
-        .iter()
-        .flat_map(|(_, subscribers)| subscribers)
-    {
-        // サブスクライバーにイベントを送信
-        // ここで、イベントがフィルタに合致するかどうかをチェックする
-        for filter in &s.filter {
-            if match_event(&event, &filter) {
-                let _ = message_sender.send(Message::Text(serde_json::to_string(&event).unwrap()));
-                break;
+        .entry(conn.who.to_string())
+        .or_insert_with(RateLimiter::new)
+        .check(now, state.config.max_events_per_minute)
+        .and_then(|_| authorize(conn, &state.config))
+        .and_then(|_| validate_event(&event, &state.config, now))
+        .and_then(|_| authorize_protected(conn, &event));
+    if let Err(rejection) = validated {
+        // 認証を必須としていないリレーでは、保護されたイベントを送るクライアントに改めてチャレンジを送る
+        if rejection.prefix == ReasonPrefix::AuthRequired && !state.config.auth_required {
+            conn.send(&ServerMessage::Auth(conn.challenge.clone()));
+        }
+        conn.send(&ServerMessage::Ok(ServerOk::rejected(
+            event.id,
+            rejection.prefix,
+            &rejection.message,
+        )));
+        return Ok(());
+    }
+
+    // REQの処理と競合しないように、保存から配信まで読み込みロックを保持する
+    let subscribers = state.subscribers.read().await;
+
+    // イベントを保存し、削除リクエストの場合は参照されたイベントを削除
+    let saving = event.clone();
+    let saved = run_blocking(&state.store, move |store| {
+        let saved = save_event(store, &saving);
+        if saved.is_ok() && saving.kind == EventKind::DELETION {
+            if let Err(e) = apply_deletion(store, &saving) {
+                tracing::error!("{}", e.to_string());
             }
         }
+        Ok(saved)
+    })
+    .await
+    .unwrap_or_else(|e| Err(storage_error(e)));
+    if let Err(rejection) = saved {
+        conn.send(&ServerMessage::Ok(ServerOk::rejected(
+            event.id,
+            rejection.prefix,
+            &rejection.message,
+        )));
+        return Ok(());
     }
+
+    // OKメッセージを送信
+    conn.send(&ServerMessage::Ok(ServerOk::accepted(event.id.clone())));
+
+    // サブスクライバーにイベントを配信
+    fanout(&event, &subscribers);
     Ok(())
 }
 
-fn match_event(event: &Event, filter: &Filter) -> bool {
-    contains(filter.ids.as_ref(), &event.id)
-        && (contains(filter.authors.as_ref(), &event.pubkey))
-        && (contains(filter.kinds.as_ref(), &u16::from(event.kind)))
-        && (filter.e_tags.iter().any(|e| {
-            e.iter()
-                .any(|tag| event.tags.iter().any(|t| t.contains(tag)))
-        }))
-        && (filter.p_tags.iter().any(|p| {
-            p.iter()
-                .any(|tag| event.tags.iter().any(|t| t.contains(tag)))
-        }))
-        && (filter.since.is_none() || filter.since.unwrap() < event.created_at)
-        && (filter.until.is_none() || filter.until.unwrap() > event.created_at)
+// イベントを保存し、受け付けない場合は理由を返す
+fn save_event(store: &dyn EventStore, event: &Event) -> Result<(), Rejection> {
+    // ephemeralイベントは保存せずに配信のみ行う
+    if event.kind.is_ephemeral() {
+        return Ok(());
+    }
+
+    // 作成者が削除リクエストを出しているイベントは受け付けない
+    if is_deleted(store, event).map_err(storage_error)? {
+        return Err(Rejection::new(
+            ReasonPrefix::Blocked,
+            "this event has been deleted",
+        ));
+    }
+
+    match store.save(event).map_err(storage_error)? {
+        SaveOutcome::Saved => Ok(()),
+        SaveOutcome::Duplicate => Err(Rejection::new(
+            ReasonPrefix::Duplicate,
+            "already have this event",
+        )),
+        SaveOutcome::Outdated => Err(Rejection::new(
+            ReasonPrefix::Duplicate,
+            "already have a newer version of this event",
+        )),
+    }
 }
 
-fn contains<T>(vec: Option<&Vec<T>>, item: &T) -> bool
-where
-    T: PartialEq,
-{
-    // フィルタが指定されていない場合は、常にtrueを返す
-    vec.map_or(true, |v| v.contains(item))
+// SQLiteへの読み書きはブロッキングするため、tokioのワーカースレッドを止めないように
+// ブロッキング用のスレッドで実行する
+async fn run_blocking<T: Send + 'static>(
+    store: &Arc<dyn EventStore>,
+    f: impl FnOnce(&dyn EventStore) -> Result<T, NostrError> + Send + 'static,
+) -> Result<T, NostrError> {
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || f(store.as_ref()))
+        .await
+        .map_err(|e| NostrError::Storage(e.to_string()))?
+}
+
+fn storage_error(e: NostrError) -> Rejection {
+    tracing::error!("{}", e.to_string());
+    Rejection::new(ReasonPrefix::Error, "could not save event")
 }
 
 async fn process_close_message(
     id: String,
     state: RelayState,
-    who: SocketAddr,
+    conn: &Connection,
 ) -> Result<(), NostrError> {
     // サブスクリプション登録解除
     let mut subscribers = state.subscribers.write().await;
-    if let Some(subscribers) = subscribers.get_mut(&who.to_string()) {
+    if let Some(subscribers) = subscribers.get_mut(&conn.who.to_string()) {
         subscribers.retain(|s| s.id != id);
     }
     Ok(())
 }
+
+async fn process_auth_message(
+    event: Event,
+    state: RelayState,
+    conn: &Connection,
+) -> Result<(), NostrError> {
+    // 認証イベントを検証し、成功した場合は接続に公開鍵を紐付ける
+    let verified = verify_auth_event(
+        &event,
+        &conn.challenge,
+        state.config.relay_url.as_deref(),
+        conn.host.as_deref(),
+        unix_timestamp(),
+    );
+    match verified {
+        Ok(()) => {
+            conn.authenticate(event.pubkey);
+            conn.send(&ServerMessage::Ok(ServerOk::accepted(event.id)));
+        }
+        Err(rejection) => conn.send(&ServerMessage::Ok(ServerOk::rejected(
+            event.id,
+            rejection.prefix,
+            &rejection.message,
+        ))),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use axum::extract::ws::Message;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use super::{process_nostr_message, RelayState};
+    use crate::{
+        config::RelayConfig,
+        connection::Connection,
+        event::{unix_timestamp, Event, EventKind, UnsignedEvent},
+        keys::Keys,
+        message::{
+            ClientMessage, Closed, ReasonPrefix, ServerCount, ServerMessage, ServerMessageEvent,
+            ServerOk,
+        },
+        nip59,
+        req::{Filter, Req},
+        store::MemoryStore,
+        test_util::{alice, signed_event, ALICE},
+    };
+
+    struct Client {
+        conn: Connection,
+        receiver: UnboundedReceiver<Message>,
+    }
+
+    impl Client {
+        fn new(port: u16) -> Self {
+            let (sender, receiver) = unbounded_channel();
+            Self {
+                conn: Connection::new(SocketAddr::from(([127, 0, 0, 1], port)), sender)
+                    .with_host(Some("relay.example.com".to_string())),
+                receiver,
+            }
+        }
+
+        async fn send(&self, state: &RelayState, message: ClientMessage) {
+            process_nostr_message(
+                serde_json::to_string(&message).unwrap(),
+                state.clone(),
+                &self.conn,
+            )
+            .await
+            .unwrap();
+        }
+
+        // 受信済みのメッセージをすべて取り出す
+        fn received(&mut self) -> Vec<ServerMessage> {
+            let mut messages = Vec::new();
+            while let Ok(Message::Text(t)) = self.receiver.try_recv() {
+                messages.push(serde_json::from_str(&t).unwrap());
+            }
+            messages
+        }
+    }
+
+    fn state() -> RelayState {
+        state_with(RelayConfig::default())
+    }
+
+    fn state_with(config: RelayConfig) -> RelayState {
+        RelayState::new(config, Arc::new(MemoryStore::new()))
+    }
+
+    fn auth_event(challenge: &str) -> Event {
+        signed_event(
+            ALICE,
+            22242,
+            vec![
+                vec!["relay", "wss://relay.example.com"],
+                vec!["challenge", challenge],
+            ],
+            "",
+            unix_timestamp(),
+        )
+    }
+
+    fn req(id: &str, filters: Vec<Filter>) -> ClientMessage {
+        ClientMessage::Req(Req {
+            id: id.to_string(),
+            filter: filters,
+        })
+    }
+
+    fn event_message(id: &str, event: &Event) -> ServerMessage {
+        ServerMessage::Event(ServerMessageEvent {
+            subscribe_id: id.to_string(),
+            event: event.clone(),
+        })
+    }
+
+    #[tokio::test]
+    async fn fanout_once_per_subscription() {
+        let state = state();
+        let mut publisher = Client::new(1);
+        let mut subscriber = Client::new(2);
+        subscriber
+            .send(
+                &state,
+                req(
+                    "sub",
+                    vec![Filter::new().kinds(vec![1]), Filter::new().since(0)],
+                ),
+            )
+            .await;
+        subscriber
+            .send(&state, req("other", vec![Filter::new().kinds(vec![7])]))
+            .await;
+        assert_eq!(
+            subscriber.received(),
+            vec![
+                ServerMessage::EOSE("sub".to_string()),
+                ServerMessage::EOSE("other".to_string()),
+            ]
+        );
+
+        let note = signed_event(ALICE, 1, vec![], "note", 1700000000);
+        publisher.send(&state, note.clone().into()).await;
+        assert_eq!(
+            publisher.received(),
+            vec![ServerMessage::Ok(ServerOk::accepted(note.id.clone()))]
+        );
+        assert_eq!(subscriber.received(), vec![event_message("sub", &note)]);
+    }
+
+    #[tokio::test]
+    async fn ephemeral_events_are_not_stored() {
+        let state = state();
+        let mut publisher = Client::new(1);
+        let mut subscriber = Client::new(2);
+        subscriber
+            .send(&state, req("live", vec![Filter::new().kinds(vec![20001])]))
+            .await;
+        subscriber.received();
+
+        let typing = signed_event(ALICE, 20001, vec![], "typing", 1700000000);
+        publisher.send(&state, typing.clone().into()).await;
+        assert_eq!(
+            publisher.received(),
+            vec![ServerMessage::Ok(ServerOk::accepted(typing.id.clone()))]
+        );
+        assert_eq!(subscriber.received(), vec![event_message("live", &typing)]);
+
+        subscriber
+            .send(
+                &state,
+                req("history", vec![Filter::new().kinds(vec![20001])]),
+            )
+            .await;
+        assert_eq!(
+            subscriber.received(),
+            vec![ServerMessage::EOSE("history".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuse_republishing_deleted_event() {
+        let state = state();
+        let mut client = Client::new(1);
+        let note = signed_event(ALICE, 1, vec![], "note", 1700000000);
+        let deletion = signed_event(ALICE, 5, vec![vec!["e", &note.id]], "", 1700000100);
+        client.send(&state, note.clone().into()).await;
+        client.send(&state, deletion.clone().into()).await;
+        client.send(&state, note.clone().into()).await;
+        assert_eq!(
+            client.received(),
+            vec![
+                ServerMessage::Ok(ServerOk::accepted(note.id.clone())),
+                ServerMessage::Ok(ServerOk::accepted(deletion.id.clone())),
+                ServerMessage::Ok(ServerOk::rejected(
+                    note.id.clone(),
+                    ReasonPrefix::Blocked,
+                    "this event has been deleted"
+                )),
+            ]
+        );
+
+        client.send(&state, req("sub", vec![Filter::new()])).await;
+        assert_eq!(
+            client.received(),
+            vec![
+                event_message("sub", &deletion),
+                ServerMessage::EOSE("sub".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn req_returns_stored_events_then_eose() {
+        let state = state();
+        let mut client = Client::new(1);
+        let old = signed_event(ALICE, 1, vec![], "old", 1700000000);
+        let new = signed_event(ALICE, 1, vec![], "new", 1700000100);
+        let newest = signed_event(ALICE, 1, vec![], "newest", 1700000200);
+        for e in [&old, &new, &newest] {
+            client.send(&state, e.clone().into()).await;
+        }
+        assert_eq!(
+            client.received(),
+            vec![
+                ServerMessage::Ok(ServerOk::accepted(old.id.clone())),
+                ServerMessage::Ok(ServerOk::accepted(new.id.clone())),
+                ServerMessage::Ok(ServerOk::accepted(newest.id.clone())),
+            ]
+        );
+
+        client
+            .send(
+                &state,
+                req("sub", vec![Filter::new().kinds(vec![1]).limit(2)]),
+            )
+            .await;
+        assert_eq!(
+            client.received(),
+            vec![
+                event_message("sub", &newest),
+                event_message("sub", &new),
+                ServerMessage::EOSE("sub".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn require_auth_before_req_and_event() {
+        let state = state_with(RelayConfig {
+            auth_required: true,
+            relay_url: Some("wss://relay.example.com".to_string()),
+            ..Default::default()
+        });
+        let mut client = Client::new(1);
+        let note = signed_event(ALICE, 1, vec![], "note", 1700000000);
+
+        client.send(&state, req("sub", vec![Filter::new()])).await;
+        client.send(&state, note.clone().into()).await;
+        assert_eq!(
+            client.received(),
+            vec![
+                ServerMessage::Closed(Closed {
+                    subscribe_id: "sub".to_string(),
+                    message: "auth-required: authentication required".to_string(),
+                }),
+                ServerMessage::Ok(ServerOk::rejected(
+                    note.id.clone(),
+                    ReasonPrefix::AuthRequired,
+                    "authentication required"
+                )),
+            ]
+        );
+
+        // 別のチャレンジに対する認証は受け付けない
+        let wrong = auth_event("wrong");
+        client
+            .send(&state, ClientMessage::Auth(wrong.clone()))
+            .await;
+        let auth = auth_event(&client.conn.challenge);
+        client.send(&state, ClientMessage::Auth(auth.clone())).await;
+        client.send(&state, note.clone().into()).await;
+        client.send(&state, req("sub", vec![Filter::new()])).await;
+        assert_eq!(
+            client.received(),
+            vec![
+                ServerMessage::Ok(ServerOk::rejected(
+                    wrong.id.clone(),
+                    ReasonPrefix::Invalid,
+                    "auth event challenge does not match"
+                )),
+                ServerMessage::Ok(ServerOk::accepted(auth.id.clone())),
+                ServerMessage::Ok(ServerOk::accepted(note.id.clone())),
+                event_message("sub", &note),
+                ServerMessage::EOSE("sub".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn restrict_to_allowed_pubkeys() {
+        let state = state_with(RelayConfig {
+            auth_required: true,
+            allowed_pubkeys: ["0".repeat(64)].into_iter().collect(),
+            ..Default::default()
+        });
+        let mut client = Client::new(1);
+        let auth = auth_event(&client.conn.challenge);
+        client.send(&state, ClientMessage::Auth(auth.clone())).await;
+        client.send(&state, req("sub", vec![Filter::new()])).await;
+        assert_eq!(
+            client.received(),
+            vec![
+                ServerMessage::Ok(ServerOk::accepted(auth.id.clone())),
+                ServerMessage::Closed(Closed {
+                    subscribe_id: "sub".to_string(),
+                    message: "restricted: this relay only serves its members".to_string(),
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn count_matching_events() {
+        let state = state();
+        let mut client = Client::new(1);
+        for (content, created_at) in [("a", 1700000000), ("b", 1700000001)] {
+            client
+                .send(
+                    &state,
+                    signed_event(ALICE, 1, vec![], content, created_at).into(),
+                )
+                .await;
+        }
+        client
+            .send(
+                &state,
+                signed_event(ALICE, 7, vec![], "+", 1700000002).into(),
+            )
+            .await;
+        client.received();
+
+        // limitは件数に影響しない
+        client
+            .send(
+                &state,
+                ClientMessage::Count(Req {
+                    id: "count".to_string(),
+                    filter: vec![Filter::new().kinds(vec![1]).limit(1)],
+                }),
+            )
+            .await;
+        assert_eq!(
+            client.received(),
+            vec![ServerMessage::Count(ServerCount {
+                subscribe_id: "count".to_string(),
+                count: 2,
+                approximate: false,
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_gift_wraps_for_others_before_limit() {
+        let state = state();
+        let mut client = Client::new(1);
+        let notes: Vec<Event> = [("a", 1700000000), ("b", 1700000001)]
+            .into_iter()
+            .map(|(content, created_at)| signed_event(ALICE, 1, vec![], content, created_at))
+            .collect();
+        for note in &notes {
+            client.send(&state, note.clone().into()).await;
+        }
+        // 他人宛てのgift wrapの方が新しい
+        let sender = Keys::generate();
+        let recipient = Keys::generate().public_key();
+        let rumor = UnsignedEvent::new(
+            sender.public_key(),
+            EventKind::CHAT_MESSAGE,
+            vec![vec!["p".to_string(), recipient.clone()]],
+            "hi".to_string(),
+            unix_timestamp(),
+        );
+        for _ in 0..2 {
+            let wrap = nip59::wrap(&sender, &recipient, &rumor).unwrap();
+            assert!(wrap.created_at > notes[1].created_at);
+            client.send(&state, wrap.into()).await;
+        }
+        client.received();
+
+        client
+            .send(&state, req("sub", vec![Filter::new().limit(2)]))
+            .await;
+        assert_eq!(
+            client.received(),
+            vec![
+                event_message("sub", &notes[1]),
+                event_message("sub", &notes[0]),
+                ServerMessage::EOSE("sub".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn accept_protected_event_from_authenticated_author() {
+        let state = state();
+        let mut client = Client::new(1);
+        let protected = signed_event(ALICE, 1, vec![vec!["-"]], "members only", 1700000000);
+
+        client.send(&state, protected.clone().into()).await;
+        let auth = auth_event(&client.conn.challenge);
+        client.send(&state, ClientMessage::Auth(auth.clone())).await;
+        client.send(&state, protected.clone().into()).await;
+        assert_eq!(
+            client.received(),
+            vec![
+                ServerMessage::Auth(client.conn.challenge.clone()),
+                ServerMessage::Ok(ServerOk::rejected(
+                    protected.id.clone(),
+                    ReasonPrefix::AuthRequired,
+                    "this event may only be published by its author"
+                )),
+                ServerMessage::Ok(ServerOk::accepted(auth.id.clone())),
+                ServerMessage::Ok(ServerOk::accepted(protected.id.clone())),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_gift_wraps_only_to_authenticated_recipient() {
+        let state = state();
+        let publisher = Client::new(1);
+        let mut recipient = Client::new(2);
+        let recipient_keys = alice();
+        let sender = Keys::generate();
+        let rumor = UnsignedEvent::new(
+            sender.public_key(),
+            EventKind::CHAT_MESSAGE,
+            vec![vec!["p".to_string(), recipient_keys.public_key()]],
+            "hi".to_string(),
+            unix_timestamp(),
+        );
+        let stored = nip59::wrap(&sender, &recipient_keys.public_key(), &rumor).unwrap();
+        publisher.send(&state, stored.clone().into()).await;
+
+        // 認証前はgift wrapを要求するREQを拒否し、それ以外のREQにも含めない
+        recipient
+            .send(&state, req("dm", vec![Filter::new().kinds(vec![1059])]))
+            .await;
+        recipient
+            .send(&state, req("all", vec![Filter::new()]))
+            .await;
+        assert_eq!(
+            recipient.received(),
+            vec![
+                ServerMessage::Auth(recipient.conn.challenge.clone()),
+                ServerMessage::Closed(Closed {
+                    subscribe_id: "dm".to_string(),
+                    message: "auth-required: gift wraps are only served to their recipients"
+                        .to_string(),
+                }),
+                ServerMessage::EOSE("all".to_string()),
+            ]
+        );
+
+        let auth = auth_event(&recipient.conn.challenge);
+        recipient.send(&state, ClientMessage::Auth(auth)).await;
+        recipient
+            .send(&state, req("dm", vec![Filter::new().kinds(vec![1059])]))
+            .await;
+        let received = recipient.received();
+        assert_eq!(
+            received[1..],
+            [
+                event_message("dm", &stored),
+                ServerMessage::EOSE("dm".to_string()),
+            ]
+        );
+
+        // 新しいgift wrapは受信者のサブスクリプションにのみ配信する
+        let mut other = Client::new(3);
+        other.send(&state, req("all", vec![Filter::new()])).await;
+        other.received();
+        let live = nip59::wrap(&sender, &recipient_keys.public_key(), &rumor).unwrap();
+        publisher.send(&state, live.clone().into()).await;
+        assert_eq!(
+            recipient.received(),
+            vec![event_message("all", &live), event_message("dm", &live),]
+        );
+        assert_eq!(other.received(), vec![]);
+
+        // COUNTも受け取れるイベントだけを数える
+        other
+            .send(
+                &state,
+                ClientMessage::Count(Req {
+                    id: "count".to_string(),
+                    filter: vec![Filter::new()],
+                }),
+            )
+            .await;
+        assert_eq!(
+            other.received(),
+            vec![ServerMessage::Count(ServerCount {
+                subscribe_id: "count".to_string(),
+                count: 0,
+                approximate: false,
+            })]
+        );
+        recipient
+            .send(
+                &state,
+                ClientMessage::Count(Req {
+                    id: "count".to_string(),
+                    filter: vec![
+                        Filter::new(),
+                        Filter::new()
+                            .p_tags(vec![recipient_keys.public_key(), sender.public_key()]),
+                    ],
+                }),
+            )
+            .await;
+        assert_eq!(
+            recipient.received(),
+            vec![ServerMessage::Count(ServerCount {
+                subscribe_id: "count".to_string(),
+                count: 2,
+                approximate: false,
+            })]
+        );
+    }
+}
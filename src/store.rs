@@ -0,0 +1,394 @@
+mod indexed;
+mod memory;
+mod sqlite;
+
+use std::collections::HashSet;
+
+pub use indexed::IndexedStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use crate::{error::NostrError, event::Event, req::Filter};
+
+// イベントを保存した結果
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SaveOutcome {
+    // 新しく保存した
+    Saved,
+    // 同じIDのイベントが既に保存されている
+    Duplicate,
+    // replaceable・addressableイベントで、より新しいイベントが既に保存されている
+    Outdated,
+}
+
+// イベントの保存先
+// 実装はsaveでreplaceable・addressableイベントの置き換えを行い、
+// query_filterで1つのフィルタに合致する有効期限切れでないイベントを新しい順に、limitを守って返す
+#[allow(dead_code)]
+pub trait EventStore: Send + Sync {
+    fn save(&self, event: &Event) -> Result<SaveOutcome, NostrError>;
+
+    fn query_filter(&self, filter: &Filter) -> Result<Vec<Event>, NostrError>;
+
+    // フィルタに合致するイベントを削除し、削除した件数を返す
+    fn delete(&self, filter: &Filter) -> Result<usize, NostrError>;
+
+    // nowの時点で有効期限が切れているイベントを削除し、削除した件数を返す
+    fn purge_expired(&self, now: i64) -> Result<usize, NostrError>;
+
+    // いずれかのフィルタに合致するイベントを重複なしで新しい順に返す
+    fn query(&self, filters: &[Filter]) -> Result<Vec<Event>, NostrError> {
+        let mut seen = HashSet::new();
+        let mut events = Vec::new();
+        for filter in filters {
+            for event in self.query_filter(filter)? {
+                if seen.insert(event.id.clone()) {
+                    events.push(event);
+                }
+            }
+        }
+        sort_newest_first(&mut events);
+        Ok(events)
+    }
+
+    // いずれかのフィルタに合致するイベントの件数（limitは無視する）
+    fn count(&self, filters: &[Filter]) -> Result<usize, NostrError> {
+        count_by_query(self, filters)
+    }
+}
+
+// 合致するイベントを全て読み込んで数える
+pub fn count_by_query<S: EventStore + ?Sized>(
+    store: &S,
+    filters: &[Filter],
+) -> Result<usize, NostrError> {
+    let filters: Vec<Filter> = filters
+        .iter()
+        .map(|f| Filter {
+            limit: None,
+            ..f.clone()
+        })
+        .collect();
+    Ok(store.query(&filters)?.len())
+}
+
+// created_atの新しい順、同じ場合はIDの昇順に並べる
+pub fn sort_newest_first(events: &mut [Event]) {
+    events.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+// 置き換えの対象となる同じpubkey・kind（addressableの場合は"d"タグも）のイベントかどうか
+pub fn same_address(a: &Event, b: &Event) -> bool {
+    (a.kind.is_replaceable() || a.kind.is_addressable())
+        && a.pubkey == b.pubkey
+        && a.kind == b.kind
+        && (!a.kind.is_addressable() || a.d_tag() == b.d_tag())
+}
+
+// newがoldを置き換えるかどうか
+// created_atが新しい方を残し、同じ場合はIDが辞書順で小さい方を残す
+pub fn replaces(new: &Event, old: &Event) -> bool {
+    new.created_at > old.created_at || (new.created_at == old.created_at && new.id < old.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventStore, IndexedStore, MemoryStore, SaveOutcome, SqliteStore};
+    use crate::{
+        config::RelayConfig,
+        delegation::{delegation_tag, Conditions},
+        event::{unix_timestamp, EventKind},
+        keys::Keys,
+        req::Filter,
+        test_util::{signed_event, ALICE},
+        validation::validate_event,
+    };
+
+    fn stores() -> Vec<Box<dyn EventStore>> {
+        vec![
+            Box::new(MemoryStore::new()),
+            Box::new(SqliteStore::open_in_memory().unwrap()),
+        ]
+    }
+
+    #[test]
+    fn save_and_query() {
+        for store in stores() {
+            let old = signed_event(ALICE, 1, vec![], "old", 100);
+            let new = signed_event(ALICE, 1, vec![], "new", 200);
+            let meta = signed_event(ALICE, 0, vec![], "{}", 150);
+            assert_eq!(store.save(&old).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&new).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&meta).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&old).unwrap(), SaveOutcome::Duplicate);
+
+            let events = store.query(&[Filter::new()]).unwrap();
+            assert_eq!(events, vec![new.clone(), meta.clone(), old.clone()]);
+
+            let events = store
+                .query(&[Filter::new().kinds(vec![1]).limit(1)])
+                .unwrap();
+            assert_eq!(events, vec![new.clone()]);
+
+            let events = store
+                .query(&[
+                    Filter::new().ids(vec![old.id.clone()]),
+                    Filter::new().since(150).until(150),
+                ])
+                .unwrap();
+            assert_eq!(events, vec![meta.clone(), old.clone()]);
+        }
+    }
+
+    #[test]
+    fn query_by_tag() {
+        for store in stores() {
+            let tagged = signed_event(ALICE, 1, vec![vec!["p", "pubkey1"]], "tagged", 100);
+            let untagged = signed_event(ALICE, 1, vec![], "untagged", 100);
+            store.save(&tagged).unwrap();
+            store.save(&untagged).unwrap();
+            let events = store
+                .query(&[Filter::new().p_tags(vec!["pubkey1".to_string()])])
+                .unwrap();
+            assert_eq!(events, vec![tagged]);
+        }
+    }
+
+    #[test]
+    fn keep_newest_replaceable() {
+        for store in stores() {
+            let old = signed_event(ALICE, 0, vec![], "old", 100);
+            let new = signed_event(ALICE, 0, vec![], "new", 200);
+            let contacts = signed_event(ALICE, 3, vec![], "", 100);
+            assert_eq!(store.save(&old).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&contacts).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&new).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&old).unwrap(), SaveOutcome::Outdated);
+            let events = store.query(&[Filter::new()]).unwrap();
+            assert_eq!(events, vec![new.clone(), contacts.clone()]);
+
+            // created_atが同じ場合はIDが小さい方を残す
+            let a = signed_event(ALICE, 10000, vec![], "a", 300);
+            let b = signed_event(ALICE, 10000, vec![], "b", 300);
+            let (lower, higher) = if a.id < b.id { (a, b) } else { (b, a) };
+            assert_eq!(store.save(&higher).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&lower).unwrap(), SaveOutcome::Saved);
+            assert_eq!(store.save(&higher).unwrap(), SaveOutcome::Outdated);
+            let events = store.query(&[Filter::new().kinds(vec![10000])]).unwrap();
+            assert_eq!(events, vec![lower]);
+        }
+    }
+
+    #[test]
+    fn keep_newest_addressable_per_d_tag() {
+        for store in stores() {
+            let first = signed_event(ALICE, 30023, vec![vec!["d", "article"]], "v1", 100);
+            let second = signed_event(ALICE, 30023, vec![vec!["d", "article"]], "v2", 200);
+            let other = signed_event(ALICE, 30023, vec![vec!["d", "other"]], "other", 100);
+            let no_d = signed_event(ALICE, 30023, vec![], "no d", 100);
+            for e in [&first, &other, &no_d, &second] {
+                assert_eq!(store.save(e).unwrap(), SaveOutcome::Saved);
+            }
+            assert_eq!(store.save(&first).unwrap(), SaveOutcome::Outdated);
+            let events = store.query(&[Filter::new().kinds(vec![30023])]).unwrap();
+            assert_eq!(events.len(), 3);
+            assert!(events.contains(&second));
+            assert!(!events.contains(&first));
+        }
+    }
+
+    #[test]
+    fn hide_and_purge_expired() {
+        let now = unix_timestamp();
+        for store in stores() {
+            let expired = signed_event(
+                ALICE,
+                1,
+                vec![vec!["expiration", &(now - 1).to_string()]],
+                "a",
+                100,
+            );
+            let expiring = signed_event(
+                ALICE,
+                1,
+                vec![vec!["expiration", &(now + 60).to_string()]],
+                "b",
+                100,
+            );
+            let permanent = signed_event(ALICE, 1, vec![], "c", 100);
+            for e in [&expired, &expiring, &permanent] {
+                store.save(e).unwrap();
+            }
+            let events = store.query(&[Filter::new()]).unwrap();
+            assert_eq!(events.len(), 2);
+            assert!(!events.contains(&expired));
+
+            assert_eq!(store.purge_expired(now).unwrap(), 1);
+            assert_eq!(store.purge_expired(now + 60).unwrap(), 1);
+            assert_eq!(store.query(&[Filter::new()]).unwrap(), vec![permanent]);
+        }
+    }
+
+    #[test]
+    fn delete_and_count() {
+        for store in stores() {
+            store
+                .save(&signed_event(ALICE, 1, vec![], "a", 100))
+                .unwrap();
+            store
+                .save(&signed_event(ALICE, 1, vec![], "b", 200))
+                .unwrap();
+            store
+                .save(&signed_event(ALICE, 7, vec![], "+", 300))
+                .unwrap();
+            assert_eq!(store.count(&[Filter::new().limit(1)]).unwrap(), 3);
+            assert_eq!(store.delete(&Filter::new().kinds(vec![1])).unwrap(), 2);
+            assert_eq!(store.count(&[Filter::new()]).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn count_without_loading_events() {
+        let now = unix_timestamp();
+        for store in stores() {
+            let note = signed_event(ALICE, 1, vec![vec!["t", "nostr"]], "a", 100);
+            let reaction = signed_event(ALICE, 7, vec![], "+", 200);
+            let expired = signed_event(
+                ALICE,
+                1,
+                vec![vec!["expiration", &(now - 1).to_string()]],
+                "b",
+                300,
+            );
+            for e in [&note, &reaction, &expired] {
+                store.save(e).unwrap();
+            }
+            assert_eq!(store.count(&[]).unwrap(), 0);
+            assert_eq!(store.count(&[Filter::new()]).unwrap(), 2);
+            // 複数のフィルタに合致するイベントは1件と数える
+            let filters = [
+                Filter::new().kinds(vec![1]),
+                Filter::new().since(100).limit(1),
+                Filter::new().ids(vec![reaction.id.clone()]),
+            ];
+            assert_eq!(store.count(&filters).unwrap(), 2);
+            assert_eq!(
+                store
+                    .count(&[Filter::new().authors(vec![note.pubkey.clone()]).until(150)])
+                    .unwrap(),
+                1
+            );
+            // タグの条件を含む場合も数えられる
+            let filters = [
+                Filter::new().tag('t', vec!["nostr".to_string()]),
+                Filter::new().kinds(vec![7]),
+            ];
+            assert_eq!(store.count(&filters).unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn query_delegated_events_by_delegator() {
+        let delegator = "0000000000000000000000000000000000000000000000000000000000000003";
+        let delegator_pubkey = Keys::parse(delegator).unwrap().public_key();
+        for store in stores() {
+            let own = signed_event(ALICE, 1, vec![], "own", 100);
+            let conditions = Conditions {
+                kinds: vec![EventKind::TEXT_NOTE],
+                ..Default::default()
+            };
+            let tag = delegation_tag(delegator, &own.pubkey, &conditions).unwrap();
+            let delegated = signed_event(
+                ALICE,
+                1,
+                vec![tag.iter().map(String::as_str).collect()],
+                "delegated",
+                200,
+            );
+            // リレーが受け付けるのと同じ検証を通るタグであること
+            assert_eq!(
+                validate_event(&delegated, &RelayConfig::default(), unix_timestamp()),
+                Ok(())
+            );
+            store.save(&own).unwrap();
+            store.save(&delegated).unwrap();
+            let events = store
+                .query(&[Filter::new()
+                    .authors(vec![delegator_pubkey.clone()])
+                    .include_delegated()])
+                .unwrap();
+            assert_eq!(events, vec![delegated.clone()]);
+            // リレー内部のフィルタでは委任者の公開鍵と照合しない
+            let events = store
+                .query(&[Filter::new().authors(vec![delegator_pubkey.clone()])])
+                .unwrap();
+            assert_eq!(events, vec![]);
+            let events = store
+                .query(&[Filter::new().authors(vec![own.pubkey.clone()])])
+                .unwrap();
+            assert_eq!(events, vec![delegated, own]);
+        }
+    }
+
+    #[test]
+    fn search_by_relevance() {
+        let indexed = |inner: Box<dyn EventStore>| -> Box<dyn EventStore> {
+            Box::new(IndexedStore::new(inner).unwrap())
+        };
+        let plain: Vec<Box<dyn EventStore>> = stores();
+        for (store, plain) in stores().into_iter().map(indexed).zip(plain) {
+            let once = signed_event(
+                ALICE,
+                1,
+                vec![],
+                "Nostr relays forward notes between many clients",
+                300,
+            );
+            let twice = signed_event(ALICE, 1, vec![], "nostr, nostr!", 100);
+            let other = signed_event(ALICE, 1, vec![], "unrelated note", 200);
+            for e in [&once, &twice, &other] {
+                store.save(e).unwrap();
+                plain.save(e).unwrap();
+            }
+            let filter = Filter::new().search("NOSTR");
+
+            // インデックスがある場合は関連度の高い順
+            assert_eq!(
+                store.query(std::slice::from_ref(&filter)).unwrap(),
+                vec![twice.clone(), once.clone()]
+            );
+            assert_eq!(
+                store.query(&[filter.clone().limit(1)]).unwrap(),
+                vec![twice.clone()]
+            );
+            // インデックスがない場合も絞り込みは行い、新しい順に返す
+            assert_eq!(
+                plain.query(std::slice::from_ref(&filter)).unwrap(),
+                vec![once.clone(), twice.clone()]
+            );
+
+            // 削除したイベントは検索結果に含まれない
+            store
+                .delete(&Filter::new().ids(vec![twice.id.clone()]))
+                .unwrap();
+            assert_eq!(store.query(&[filter]).unwrap(), vec![once]);
+        }
+    }
+
+    #[test]
+    fn sqlite_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("nostr-test-{}.db", std::process::id()));
+        let saved = signed_event(ALICE, 1, vec![], "persistent", 100);
+        SqliteStore::open(&path).unwrap().save(&saved).unwrap();
+        let events = SqliteStore::open(&path)
+            .unwrap()
+            .query(&[Filter::new()])
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(events, vec![saved]);
+    }
+}
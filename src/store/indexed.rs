@@ -0,0 +1,155 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use super::{count_by_query, sort_newest_first, EventStore, SaveOutcome};
+use crate::{
+    error::NostrError,
+    event::Event,
+    req::Filter,
+    search::{query_terms, SearchIndex},
+};
+
+// 一度に問い合わせるIDの数（SQLiteのパラメータ数の上限を超えないようにする）
+const ID_CHUNK: usize = 500;
+
+// NIP-50: 別のストアにイベントの内容の全文検索用のインデックスを付け加える
+// searchを指定したフィルタは関連度の高い順に、それ以外はこれまで通り新しい順に返す
+pub struct IndexedStore {
+    inner: Box<dyn EventStore>,
+    index: RwLock<SearchIndex>,
+}
+
+impl IndexedStore {
+    // 保存済みのイベントからインデックスを作成する
+    pub fn new(inner: Box<dyn EventStore>) -> Result<Self, NostrError> {
+        let mut index = SearchIndex::new();
+        for event in inner.query(&[Filter::new()])? {
+            index.add(&event.id, &event.content);
+        }
+        Ok(Self {
+            inner,
+            index: RwLock::new(index),
+        })
+    }
+
+    // searchを指定したフィルタに合致するイベントをスコアの高い順に返す
+    fn search(&self, filter: &Filter, terms: &[String]) -> Result<Vec<(Event, f64)>, NostrError> {
+        let hits = self.index.read().map_err(lock_error)?.search(terms);
+        let mut events = HashMap::new();
+        for chunk in hits.chunks(ID_CHUNK) {
+            let ids = chunk.iter().map(|(id, _)| id.clone()).collect();
+            for event in self.inner.query_filter(&Filter::new().ids(ids))? {
+                events.insert(event.id.clone(), event);
+            }
+        }
+
+        // 置き換え・削除・有効期限切れでストアから返されなくなったイベントはインデックスからも除く
+        let stale: Vec<&String> = hits
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| !events.contains_key(*id))
+            .collect();
+        if !stale.is_empty() {
+            let mut index = self.index.write().map_err(lock_error)?;
+            for id in stale {
+                index.remove(id);
+            }
+        }
+
+        Ok(hits
+            .iter()
+            .filter_map(|(id, score)| events.remove(id).map(|e| (e, *score)))
+            .filter(|(e, _)| filter.match_event(e))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    fn query_scored(&self, filter: &Filter) -> Result<Vec<(Event, f64)>, NostrError> {
+        match search_terms(filter) {
+            Some(terms) => self.search(filter, &terms),
+            None => Ok(self
+                .inner
+                .query_filter(filter)?
+                .into_iter()
+                .map(|e| (e, 0.0))
+                .collect()),
+        }
+    }
+}
+
+impl EventStore for IndexedStore {
+    fn save(&self, event: &Event) -> Result<SaveOutcome, NostrError> {
+        let outcome = self.inner.save(event)?;
+        if outcome == SaveOutcome::Saved {
+            self.index
+                .write()
+                .map_err(lock_error)?
+                .add(&event.id, &event.content);
+        }
+        Ok(outcome)
+    }
+
+    fn query_filter(&self, filter: &Filter) -> Result<Vec<Event>, NostrError> {
+        Ok(self
+            .query_scored(filter)?
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect())
+    }
+
+    fn delete(&self, filter: &Filter) -> Result<usize, NostrError> {
+        let deleted = self.inner.query_filter(&Filter {
+            limit: None,
+            ..filter.clone()
+        })?;
+        let count = self.inner.delete(filter)?;
+        let mut index = self.index.write().map_err(lock_error)?;
+        for event in deleted {
+            index.remove(&event.id);
+        }
+        Ok(count)
+    }
+
+    fn purge_expired(&self, now: i64) -> Result<usize, NostrError> {
+        // インデックスからは検索時に取り除く
+        self.inner.purge_expired(now)
+    }
+
+    // 複数のフィルタに合致したイベントは最も高いスコアを使い、スコアの高い順、同じ場合は新しい順に返す
+    fn query(&self, filters: &[Filter]) -> Result<Vec<Event>, NostrError> {
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut events = Vec::new();
+        for filter in filters {
+            for (event, score) in self.query_scored(filter)? {
+                let best = scores.entry(event.id.clone()).or_insert(score);
+                *best = best.max(score);
+                if seen.insert(event.id.clone()) {
+                    events.push(event);
+                }
+            }
+        }
+        sort_newest_first(&mut events);
+        events.sort_by(|a, b| scores[&b.id].total_cmp(&scores[&a.id]));
+        Ok(events)
+    }
+    // 全文検索を行わない場合は元のストアで数える
+    fn count(&self, filters: &[Filter]) -> Result<usize, NostrError> {
+        if filters.iter().all(|f| search_terms(f).is_none()) {
+            return self.inner.count(filters);
+        }
+        count_by_query(self, filters)
+    }
+}
+
+// 検索する単語がある場合のみ全文検索を行う
+fn search_terms(filter: &Filter) -> Option<Vec<String>> {
+    let terms = query_terms(filter.search.as_deref()?);
+    (!terms.is_empty()).then_some(terms)
+}
+
+fn lock_error<T>(e: std::sync::PoisonError<T>) -> NostrError {
+    NostrError::Storage(e.to_string())
+}
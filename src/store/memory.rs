@@ -0,0 +1,80 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use super::{replaces, same_address, sort_newest_first, EventStore, SaveOutcome};
+use crate::{
+    error::NostrError,
+    event::{unix_timestamp, Event},
+    req::Filter,
+};
+
+// プロセス内のメモリにイベントを保存する
+// 再起動すると内容は失われる
+pub struct MemoryStore {
+    // HashMapのkeyはイベントのID
+    events: RwLock<HashMap<String, Event>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            events: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl EventStore for MemoryStore {
+    fn save(&self, event: &Event) -> Result<SaveOutcome, NostrError> {
+        let mut events = self.events.write().map_err(lock_error)?;
+        if events.contains_key(&event.id) {
+            return Ok(SaveOutcome::Duplicate);
+        }
+        let replaced: Vec<String> = events
+            .values()
+            .filter(|e| same_address(event, e))
+            .map(|e| e.id.clone())
+            .collect();
+        if replaced.iter().any(|id| !replaces(event, &events[id])) {
+            return Ok(SaveOutcome::Outdated);
+        }
+        for id in replaced {
+            events.remove(&id);
+        }
+        events.insert(event.id.clone(), event.clone());
+        Ok(SaveOutcome::Saved)
+    }
+
+    fn query_filter(&self, filter: &Filter) -> Result<Vec<Event>, NostrError> {
+        let now = unix_timestamp();
+        let mut events: Vec<Event> = self
+            .events
+            .read()
+            .map_err(lock_error)?
+            .values()
+            .filter(|e| !e.is_expired(now) && filter.match_event(e))
+            .cloned()
+            .collect();
+        sort_newest_first(&mut events);
+        if let Some(limit) = filter.limit {
+            events.truncate(limit);
+        }
+        Ok(events)
+    }
+
+    fn delete(&self, filter: &Filter) -> Result<usize, NostrError> {
+        let mut events = self.events.write().map_err(lock_error)?;
+        let before = events.len();
+        events.retain(|_, e| !filter.match_event(e));
+        Ok(before - events.len())
+    }
+
+    fn purge_expired(&self, now: i64) -> Result<usize, NostrError> {
+        let mut events = self.events.write().map_err(lock_error)?;
+        let before = events.len();
+        events.retain(|_, e| !e.is_expired(now));
+        Ok(before - events.len())
+    }
+}
+
+fn lock_error<T>(e: std::sync::PoisonError<T>) -> NostrError {
+    NostrError::Storage(e.to_string())
+}
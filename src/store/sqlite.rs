@@ -0,0 +1,265 @@
+use std::{path::Path, sync::Mutex};
+
+use rusqlite::{params, params_from_iter, types::Value, Connection};
+
+use super::{count_by_query, replaces, same_address, EventStore, SaveOutcome};
+use crate::{
+    error::NostrError,
+    event::{unix_timestamp, Event},
+    req::Filter,
+};
+
+// SQLiteのデータベースファイルにイベントを保存する
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, NostrError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    #[allow(dead_code)]
+    pub fn open_in_memory() -> Result<Self, NostrError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, NostrError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS events (
+                id TEXT PRIMARY KEY,
+                pubkey TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                kind INTEGER NOT NULL,
+                raw TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS events_pubkey_kind ON events (pubkey, kind);
+            CREATE INDEX IF NOT EXISTS events_created_at ON events (created_at);",
+        )?;
+        add_column(&conn, "expiration", "INTEGER")?;
+        // NIP-26の委任者。以前に保存したイベントの委任は検証していないので設定しない
+        add_column(&conn, "delegator", "TEXT")?;
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS events_expiration ON events (expiration)
+             WHERE expiration IS NOT NULL;
+             CREATE INDEX IF NOT EXISTS events_delegator ON events (delegator)
+             WHERE delegator IS NOT NULL;",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, NostrError> {
+        self.conn
+            .lock()
+            .map_err(|e| NostrError::Storage(e.to_string()))
+    }
+}
+
+impl EventStore for SqliteStore {
+    fn save(&self, event: &Event) -> Result<SaveOutcome, NostrError> {
+        let raw = serde_json::to_string(event).map_err(|e| NostrError::Storage(e.to_string()))?;
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM events WHERE id = ?1)",
+            params![event.id],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(SaveOutcome::Duplicate);
+        }
+
+        // replaceable・addressableイベントは同じアドレスの古いイベントを置き換える
+        if event.kind.is_replaceable() || event.kind.is_addressable() {
+            let mut replaced = Vec::new();
+            {
+                let mut stmt =
+                    tx.prepare("SELECT raw FROM events WHERE pubkey = ?1 AND kind = ?2")?;
+                let mut rows = stmt.query(params![event.pubkey, u16::from(event.kind)])?;
+                while let Some(row) = rows.next()? {
+                    let old = parse(&row.get::<_, String>(0)?)?;
+                    if !same_address(event, &old) {
+                        continue;
+                    }
+                    if !replaces(event, &old) {
+                        return Ok(SaveOutcome::Outdated);
+                    }
+                    replaced.push(old.id);
+                }
+            }
+            for id in replaced {
+                tx.execute("DELETE FROM events WHERE id = ?1", params![id])?;
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO events (id, pubkey, created_at, kind, raw, expiration, delegator)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                event.id,
+                event.pubkey,
+                event.created_at,
+                u16::from(event.kind),
+                raw,
+                event.expiration(),
+                event.delegator()
+            ],
+        )?;
+        tx.commit()?;
+        Ok(SaveOutcome::Saved)
+    }
+
+    fn query_filter(&self, filter: &Filter) -> Result<Vec<Event>, NostrError> {
+        let conn = self.lock()?;
+        select(&conn, filter)
+    }
+
+    fn delete(&self, filter: &Filter) -> Result<usize, NostrError> {
+        let mut conn = self.lock()?;
+        let events = select(&conn, filter)?;
+        let tx = conn.transaction()?;
+        for event in &events {
+            tx.execute("DELETE FROM events WHERE id = ?1", params![event.id])?;
+        }
+        tx.commit()?;
+        Ok(events.len())
+    }
+
+    fn purge_expired(&self, now: i64) -> Result<usize, NostrError> {
+        Ok(self.lock()?.execute(
+            "DELETE FROM events WHERE expiration IS NOT NULL AND expiration <= ?1",
+            params![now],
+        )?)
+    }
+
+    // カラムで絞り込める条件だけの場合は、イベントを読み込まずにSQLで数える
+    fn count(&self, filters: &[Filter]) -> Result<usize, NostrError> {
+        if filters.is_empty() {
+            return Ok(0);
+        }
+        if filters
+            .iter()
+            .any(|f| !f.tags.is_empty() || f.search.is_some() || f.min_pow.is_some())
+        {
+            return count_by_query(self, filters);
+        }
+
+        let mut values = vec![Value::Integer(unix_timestamp())];
+        let mut clauses = Vec::new();
+        for filter in filters {
+            let (conditions, filter_values) = column_conditions(filter);
+            clauses.push(if conditions.is_empty() {
+                "1".to_string()
+            } else {
+                format!("({})", conditions.join(" AND "))
+            });
+            values.extend(filter_values);
+        }
+        let sql = format!(
+            "SELECT COUNT(*) FROM events
+             WHERE (expiration IS NULL OR expiration > ?) AND ({})",
+            clauses.join(" OR ")
+        );
+        let count: i64 = self
+            .lock()?
+            .query_row(&sql, params_from_iter(values), |row| row.get(0))?;
+        Ok(count as usize)
+    }
+}
+
+// 既存のデータベースにカラムがない場合は追加する
+fn add_column(conn: &Connection, column: &str, definition: &str) -> Result<(), NostrError> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('events') WHERE name = ?1)",
+        params![column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE events ADD COLUMN {} {};",
+            column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+// カラムで絞り込める条件はSQLで絞り込み、タグなどの残りの条件はFilter::match_eventで確認する
+fn select(conn: &Connection, filter: &Filter) -> Result<Vec<Event>, NostrError> {
+    let limit = filter.limit.unwrap_or(usize::MAX);
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    // 有効期限切れのイベントは返さない
+    let mut conditions = vec!["(expiration IS NULL OR expiration > ?)".to_string()];
+    let mut values = vec![Value::Integer(unix_timestamp())];
+    let (filter_conditions, filter_values) = column_conditions(filter);
+    conditions.extend(filter_conditions);
+    values.extend(filter_values);
+
+    let sql = format!(
+        "SELECT raw FROM events WHERE {} ORDER BY created_at DESC, id ASC",
+        conditions.join(" AND ")
+    );
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params_from_iter(values))?;
+    let mut events = Vec::new();
+    while let Some(row) = rows.next()? {
+        let event = parse(&row.get::<_, String>(0)?)?;
+        if filter.match_event(&event) {
+            events.push(event);
+            if events.len() >= limit {
+                break;
+            }
+        }
+    }
+    Ok(events)
+}
+
+// フィルタの条件のうち、カラムで絞り込めるもののSQLと値
+fn column_conditions(filter: &Filter) -> (Vec<String>, Vec<Value>) {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    if let Some(ids) = &filter.ids {
+        conditions.push(in_clause("id", ids.len()));
+        values.extend(ids.iter().map(|v| Value::Text(v.clone())));
+    }
+    if let Some(authors) = &filter.authors {
+        if filter.delegated {
+            // 委任されたイベントは委任者の公開鍵でも検索できる
+            conditions.push(format!(
+                "({} OR {})",
+                in_clause("pubkey", authors.len()),
+                in_clause("delegator", authors.len())
+            ));
+            values.extend(authors.iter().map(|v| Value::Text(v.clone())));
+        } else {
+            conditions.push(in_clause("pubkey", authors.len()));
+        }
+        values.extend(authors.iter().map(|v| Value::Text(v.clone())));
+    }
+    if let Some(kinds) = &filter.kinds {
+        conditions.push(in_clause("kind", kinds.len()));
+        values.extend(kinds.iter().map(|v| Value::Integer(*v as i64)));
+    }
+    if let Some(since) = filter.since {
+        conditions.push("created_at >= ?".to_string());
+        values.push(Value::Integer(since));
+    }
+    if let Some(until) = filter.until {
+        conditions.push("created_at <= ?".to_string());
+        values.push(Value::Integer(until));
+    }
+    (conditions, values)
+}
+
+fn parse(raw: &str) -> Result<Event, NostrError> {
+    serde_json::from_str(raw).map_err(|e| NostrError::Storage(e.to_string()))
+}
+
+fn in_clause(column: &str, len: usize) -> String {
+    format!("{} IN ({})", column, vec!["?"; len].join(", "))
+}
@@ -1,11 +1,31 @@
 use axum::extract::ws::Message;
 use tokio::sync::mpsc::UnboundedSender;
 
-use crate::req::Filter;
+use crate::{
+    auth::can_receive, connection::AuthedPubkeys, event::Event, message::ServerMessage, req::Filter,
+};
 
+#[allow(dead_code)]
 pub struct Subscriber {
     pub client: String,
     pub sender: UnboundedSender<Message>,
     pub id: String,
     pub filter: Vec<Filter>,
+    // 購読した接続で認証済みの公開鍵
+    pub authed_pubkeys: AuthedPubkeys,
+}
+
+impl Subscriber {
+    // いずれかのフィルタに合致し、接続がイベントを受け取れるかどうか
+    pub fn matches(&self, event: &Event) -> bool {
+        self.filter.iter().any(|f| f.match_event(event)) && can_receive(&self.authed_pubkeys, event)
+    }
+
+    // サブスクライバーの接続にメッセージを送信する
+    // 接続が既に閉じられている場合はfalseを返す
+    pub fn send(&self, message: &ServerMessage) -> bool {
+        self.sender
+            .send(Message::Text(serde_json::to_string(message).unwrap()))
+            .is_ok()
+    }
 }
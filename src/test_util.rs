@@ -0,0 +1,39 @@
+// 各モジュールのテストで共通に使う鍵とイベント
+
+use crate::{
+    event::{Event, EventKind},
+    keys::Keys,
+};
+
+// テスト用の秘密鍵
+pub const ALICE: &str = "b4bfbc54ae10d5f04add7edfa4adf6e1e7514d68b6897f1d9c601aef6f785db4";
+pub const BOB: &str = "0000000000000000000000000000000000000000000000000000000000000003";
+
+pub fn alice() -> Keys {
+    Keys::parse(ALICE).unwrap()
+}
+
+pub fn bob() -> Keys {
+    Keys::parse(BOB).unwrap()
+}
+
+// 秘密鍵の持ち主が作成して署名したイベント
+pub fn signed_event(
+    seckey: &str,
+    kind: u16,
+    tags: Vec<Vec<&str>>,
+    content: &str,
+    created_at: i64,
+) -> Event {
+    Keys::parse(seckey)
+        .unwrap()
+        .sign_event(
+            EventKind::from(kind),
+            tags.into_iter()
+                .map(|t| t.into_iter().map(str::to_string).collect())
+                .collect(),
+            content.to_string(),
+            created_at,
+        )
+        .unwrap()
+}
@@ -0,0 +1,298 @@
+use crate::{
+    config::RelayConfig,
+    delegation::verify_delegation,
+    error::NostrError,
+    event::{Event, EventKind},
+    message::ReasonPrefix,
+    pow::event_difficulty,
+};
+
+// イベントを受け付けなかった理由
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub prefix: ReasonPrefix,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new(prefix: ReasonPrefix, message: &str) -> Self {
+        Self {
+            prefix,
+            message: message.to_string(),
+        }
+    }
+}
+
+// リレーが受信したイベントを検証する
+// 形式 → id → 署名 → リレーのポリシーの順に確認し、最初に見つかった問題を返す
+pub fn validate_event(event: &Event, config: &RelayConfig, now: i64) -> Result<(), Rejection> {
+    validate_format(event)?;
+
+    event.verify().map_err(|e| match e {
+        NostrError::IdMismatch => Rejection::new(ReasonPrefix::Invalid, "event id does not match"),
+        NostrError::InvalidKey(_) => Rejection::new(ReasonPrefix::Invalid, "invalid pubkey"),
+        _ => Rejection::new(ReasonPrefix::Invalid, "signature verification failed"),
+    })?;
+
+    // 認証イベントはAUTHメッセージでのみ受け付け、配信もしない
+    if event.kind == EventKind::CLIENT_AUTH {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "auth events must be sent with AUTH",
+        ));
+    }
+    let delegator = verify_delegation(event)
+        .map_err(|e| Rejection::new(ReasonPrefix::Invalid, &format!("invalid delegation: {e}")))?;
+    if config.blocked_pubkeys.contains(&event.pubkey)
+        || delegator.is_some_and(|d| config.blocked_pubkeys.contains(d))
+    {
+        return Err(Rejection::new(
+            ReasonPrefix::Blocked,
+            "pubkey is not allowed to publish",
+        ));
+    }
+    let difficulty = event_difficulty(event);
+    if difficulty < u32::from(config.min_pow_difficulty) {
+        return Err(Rejection::new(
+            ReasonPrefix::Pow,
+            &format!(
+                "difficulty {} is less than {}",
+                difficulty, config.min_pow_difficulty
+            ),
+        ));
+    }
+    if event.is_expired(now) {
+        return Err(Rejection::new(ReasonPrefix::Invalid, "event has expired"));
+    }
+    if event.created_at > now + config.max_future_seconds {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "created_at is too far in the future",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_format(event: &Event) -> Result<(), Rejection> {
+    if !is_lower_hex(&event.id, 32) {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "id must be 32 bytes of lowercase hex",
+        ));
+    }
+    if !is_lower_hex(&event.pubkey, 32) {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "pubkey must be 32 bytes of lowercase hex",
+        ));
+    }
+    if !is_lower_hex(&event.sig, 64) {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "sig must be 64 bytes of lowercase hex",
+        ));
+    }
+    if event.created_at < 0 {
+        return Err(Rejection::new(
+            ReasonPrefix::Invalid,
+            "created_at must not be negative",
+        ));
+    }
+    if event.tags.iter().any(|t| t.is_empty()) {
+        return Err(Rejection::new(ReasonPrefix::Invalid, "empty tag"));
+    }
+    Ok(())
+}
+
+fn is_lower_hex(value: &str, bytes: usize) -> bool {
+    value.len() == bytes * 2
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// 1接続あたりのイベント数を1分単位の固定ウィンドウで制限する
+pub struct RateLimiter {
+    window_start: i64,
+    count: u32,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            window_start: 0,
+            count: 0,
+        }
+    }
+
+    // 上限内であればカウントを進めてOk、超えていればRejectionを返す
+    pub fn check(&mut self, now: i64, max_per_minute: u32) -> Result<(), Rejection> {
+        if max_per_minute == 0 {
+            return Ok(());
+        }
+        if now - self.window_start >= 60 {
+            self.window_start = now;
+            self.count = 0;
+        }
+        if self.count >= max_per_minute {
+            return Err(Rejection::new(
+                ReasonPrefix::RateLimited,
+                "too many events, slow down",
+            ));
+        }
+        self.count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_event, RateLimiter, Rejection};
+    use crate::{
+        config::RelayConfig,
+        event::{Event, EventKind, UnsignedEvent},
+        message::ReasonPrefix,
+        test_util::{alice, signed_event, ALICE},
+    };
+
+    const NOW: i64 = 1708838939;
+
+    fn note(created_at: i64) -> Event {
+        signed_event(ALICE, 1, vec![vec!["t", "nostr"]], "content", created_at)
+    }
+
+    fn prefix_of(event: &Event, config: &RelayConfig) -> Option<ReasonPrefix> {
+        validate_event(event, config, NOW).err().map(|r| r.prefix)
+    }
+
+    #[test]
+    fn accept_valid_event() {
+        let event = note(NOW);
+        assert_eq!(validate_event(&event, &RelayConfig::default(), NOW), Ok(()));
+    }
+
+    #[test]
+    fn reject_malformed_fields() {
+        let mut event = note(NOW);
+        event.id = event.id.to_uppercase();
+        assert_eq!(
+            prefix_of(&event, &RelayConfig::default()),
+            Some(ReasonPrefix::Invalid)
+        );
+
+        let mut event = note(NOW);
+        event.sig.truncate(64);
+        assert_eq!(
+            prefix_of(&event, &RelayConfig::default()),
+            Some(ReasonPrefix::Invalid)
+        );
+    }
+
+    #[test]
+    fn reject_id_mismatch() {
+        let mut event = note(NOW);
+        event.content = "tampered".to_string();
+        assert_eq!(
+            validate_event(&event, &RelayConfig::default(), NOW),
+            Err(Rejection::new(
+                ReasonPrefix::Invalid,
+                "event id does not match"
+            ))
+        );
+    }
+
+    #[test]
+    fn reject_bad_signature() {
+        let mut event = note(NOW);
+        event.sig = "0".repeat(128);
+        assert_eq!(
+            validate_event(&event, &RelayConfig::default(), NOW),
+            Err(Rejection::new(
+                ReasonPrefix::Invalid,
+                "signature verification failed"
+            ))
+        );
+    }
+
+    #[test]
+    fn reject_blocked_pubkey() {
+        let event = note(NOW);
+        let mut config = RelayConfig::default();
+        config.blocked_pubkeys.insert(event.pubkey.clone());
+        assert_eq!(prefix_of(&event, &config), Some(ReasonPrefix::Blocked));
+    }
+
+    #[test]
+    fn reject_future_event() {
+        let event = note(NOW + 3600);
+        assert_eq!(
+            prefix_of(&event, &RelayConfig::default()),
+            Some(ReasonPrefix::Invalid)
+        );
+    }
+
+    #[test]
+    fn reject_expired_event() {
+        let keys = alice();
+        let expiring = |expiration: i64| {
+            keys.sign_event(
+                EventKind::TEXT_NOTE,
+                vec![vec!["expiration".to_string(), expiration.to_string()]],
+                "content".to_string(),
+                NOW - 10,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            validate_event(&expiring(NOW), &RelayConfig::default(), NOW),
+            Err(Rejection::new(ReasonPrefix::Invalid, "event has expired"))
+        );
+        assert_eq!(
+            validate_event(&expiring(NOW + 1), &RelayConfig::default(), NOW),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn require_min_pow_difficulty() {
+        let keys = alice();
+        let mined = |target: u32| {
+            UnsignedEvent::new(
+                keys.public_key(),
+                EventKind::TEXT_NOTE,
+                vec![],
+                "content".to_string(),
+                NOW,
+            )
+            .mine(target, 2)
+            .unwrap()
+            .sign_with_keys(&keys)
+            .unwrap()
+        };
+        let config = RelayConfig {
+            min_pow_difficulty: 8,
+            ..Default::default()
+        };
+        assert_eq!(validate_event(&mined(8), &config, NOW), Ok(()));
+        // 宣言した目標が低い場合は、偶然難易度を満たしていても受け付けない
+        assert_eq!(
+            validate_event(&mined(4), &config, NOW),
+            Err(Rejection::new(
+                ReasonPrefix::Pow,
+                "difficulty 4 is less than 8"
+            ))
+        );
+    }
+
+    #[test]
+    fn rate_limit_per_minute() {
+        let mut limiter = RateLimiter::new();
+        assert!(limiter.check(NOW, 2).is_ok());
+        assert!(limiter.check(NOW, 2).is_ok());
+        assert_eq!(
+            limiter.check(NOW + 1, 2).map_err(|r| r.prefix),
+            Err(ReasonPrefix::RateLimited)
+        );
+        assert!(limiter.check(NOW + 60, 2).is_ok());
+    }
+}